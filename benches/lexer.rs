@@ -0,0 +1,101 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lang::lexer::{lex, Span, Spanned, Token};
+use lang::parser::parse;
+use regex::Regex;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The whitespace-splitting lexer `Lexer` replaced: one `Regex::new` per token and the
+/// whole file materialized before parsing starts.
+fn regex_lex(input: &str, file: &str) -> VecDeque<Spanned<Token>> {
+    let lex_token = |tok: &str| {
+        let re = Regex::new(r"^-?\d{1,10}$").unwrap();
+        if re.is_match(tok) {
+            Token::Number(tok.parse::<i64>().unwrap())
+        } else {
+            Token::Word(tok.to_string())
+        }
+    };
+    let file: Rc<str> = Rc::from(file);
+    let mut res = VecDeque::new();
+    let mut current_token = String::new();
+    let mut start = Span {
+        file: file.clone(),
+        line: 1,
+        col: 1,
+        expansion: None,
+    };
+    let (mut line, mut col) = (1usize, 1usize);
+    for c in input.chars() {
+        if !c.is_whitespace() {
+            if current_token.is_empty() {
+                start = Span {
+                    file: file.clone(),
+                    line,
+                    col,
+                    expansion: None,
+                };
+            }
+            current_token.push(c);
+        } else if !current_token.is_empty() {
+            res.push_back(Spanned {
+                node: lex_token(&current_token),
+                span: start.clone(),
+            });
+            current_token = String::new();
+        }
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    if !current_token.is_empty() {
+        res.push_back(Spanned {
+            node: lex_token(&current_token),
+            span: start,
+        });
+    }
+    res
+}
+
+/// Roughly 10 KB of nested loops, the shape of our generated programs.
+fn generated_program() -> String {
+    let chunk = "5 : @ {\n  5 : @ {\n    -1 + :\n  } ;\n  -1 + :\n} ;\n";
+    chunk.repeat(10_000 / chunk.len())
+}
+
+fn bench_lexer(c: &mut Criterion) {
+    let input = generated_program();
+    let mut group = c.benchmark_group("lex");
+    group.sample_size(10);
+    group.bench_function("regex", |b| {
+        b.iter(|| regex_lex(black_box(&input), "bench.lang").len())
+    });
+    group.bench_function("streaming", |b| {
+        b.iter(|| lex(black_box(&input), "bench.lang").count())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("lex+parse");
+    group.sample_size(10);
+    group.bench_function("regex", |b| {
+        b.iter(|| {
+            let tokens = regex_lex(black_box(&input), "bench.lang");
+            parse(tokens.into_iter().map(Ok)).unwrap().ops.len()
+        })
+    });
+    group.bench_function("streaming", |b| {
+        b.iter(|| {
+            parse(lex(black_box(&input), "bench.lang"))
+                .unwrap()
+                .ops
+                .len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_lexer);
+criterion_main!(benches);
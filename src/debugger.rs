@@ -0,0 +1,252 @@
+use lang::lexer::{lex, Token};
+use lang::parser::Program;
+use lang::vm::{Value, Vm};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::collections::BTreeSet;
+
+const HELP: &str = "\
+s, step           run one op
+n, next           run one op, stepping over word calls
+c, continue       run to the next breakpoint or the end
+b, break [N|#N]   break at source line N of the program, or at op #N; list breakpoints
+d, delete [N|#N]  remove breakpoints at line N or op #N, or all of them
+l, list           show the ops around the current one
+p, stack          show the stack
+loops             show the loop stack and call depth
+set I V           set the value I places below the top of the stack to V
+push V, pop       push or drop a value
+V is a literal: 42, 'c' or \"text\".
+q, quit           leave
+An empty line repeats the last command.";
+
+struct Debugger<'a> {
+    program: &'a Program,
+    vm: Vm,
+    /// The program's own file, which `break N` refers to.
+    file: String,
+    breakpoints: BTreeSet<usize>,
+    failed: bool,
+}
+
+impl<'a> Debugger<'a> {
+    fn stopped(&self) -> bool {
+        if self.failed {
+            println!("The program failed");
+        } else if let Some(code) = self.vm.exit_code {
+            println!("The program exited with {}", code);
+        } else if self.vm.is_finished(self.program) {
+            println!("The program finished");
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn show(&mut self) {
+        self.vm.flush();
+        if !self.stopped() {
+            let op = &self.program.ops[self.vm.pc];
+            println!("#{} {} {:?}", self.vm.pc, op.span, op.node);
+        }
+        println!("stack {:?}", self.vm.stack);
+    }
+
+    fn step(&mut self) {
+        if self.failed || self.vm.is_finished(self.program) {
+            return;
+        }
+        if let Err(e) = self.vm.step(self.program) {
+            self.vm.flush();
+            eprintln!("{}", e);
+            self.failed = true;
+        }
+    }
+
+    fn running(&self) -> bool {
+        !self.failed && !self.vm.is_finished(self.program)
+    }
+
+    /// Steps until `done` holds or a breakpoint is reached.
+    fn run_until(&mut self, done: impl Fn(&Vm) -> bool) {
+        self.step();
+        while self.running() && !done(&self.vm) && !self.breakpoints.contains(&self.vm.pc) {
+            self.step();
+        }
+    }
+
+    /// Op indices meant by `#N` or by source line `N`: the first op of each run of ops on
+    /// that line.
+    fn locations(&self, arg: &str) -> Result<Vec<usize>, String> {
+        let ops = &self.program.ops;
+        if let Some(index) = arg.strip_prefix('#') {
+            return match index.parse() {
+                Ok(index) if index < ops.len() => Ok(vec![index]),
+                _ => Err(format!("No op #{}", index)),
+            };
+        }
+        let line: usize = arg
+            .parse()
+            .map_err(|_| format!("`{}` is not a line", arg))?;
+        let on_line = |i: usize| ops[i].span.line == line && *ops[i].span.file == self.file;
+        let found: Vec<usize> = (0..ops.len())
+            .filter(|&i| on_line(i) && (i == 0 || !on_line(i - 1)))
+            .collect();
+        if found.is_empty() {
+            return Err(format!("No code on line {}", line));
+        }
+        Ok(found)
+    }
+
+    fn set(&mut self, args: &str) -> Result<(), String> {
+        let (place, value) = match args.split_once(char::is_whitespace) {
+            Some((place, value)) => (place, value),
+            None => return Err("Usage: set I V".to_string()),
+        };
+        let place: usize = place
+            .parse()
+            .map_err(|_| format!("`{}` is not a place", place))?;
+        let value = value_of(value)?;
+        let len = self.vm.stack.len();
+        if place >= len {
+            return Err(format!("The stack has only {} value(s)", len));
+        }
+        self.vm.stack[len - 1 - place] = value;
+        Ok(())
+    }
+
+    /// Runs one command; returns `false` to quit.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+        let rest = line.trim_start()[command.len()..].trim();
+        match command {
+            "s" | "step" => {
+                self.step();
+                self.show();
+            }
+            "n" | "next" => {
+                let depth = self.vm.depth();
+                self.run_until(|vm| vm.depth() <= depth);
+                self.show();
+            }
+            "c" | "continue" => {
+                self.run_until(|_| false);
+                self.show();
+            }
+            "b" | "break" => match args.first() {
+                Some(arg) => {
+                    for index in self.locations(arg)? {
+                        self.breakpoints.insert(index);
+                        let op = &self.program.ops[index];
+                        println!("Breakpoint at #{} {} {:?}", index, op.span, op.node);
+                    }
+                }
+                None => {
+                    for &index in &self.breakpoints {
+                        let op = &self.program.ops[index];
+                        println!("#{} {} {:?}", index, op.span, op.node);
+                    }
+                }
+            },
+            "d" | "delete" => match args.first() {
+                Some(arg) => {
+                    for index in self.locations(arg)? {
+                        self.breakpoints.remove(&index);
+                    }
+                }
+                None => self.breakpoints.clear(),
+            },
+            "l" | "list" => {
+                let pc = self.vm.pc;
+                let first = pc.saturating_sub(3);
+                let ops = self.program.ops.iter().enumerate().skip(first);
+                for (index, op) in ops.take(pc + 4 - first) {
+                    let marker = if index == pc { '>' } else { ' ' };
+                    let bp = if self.breakpoints.contains(&index) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    println!("{}{} #{} {} {:?}", marker, bp, index, op.span, op.node);
+                }
+            }
+            "p" | "stack" => println!("stack {:?}", self.vm.stack),
+            "loops" => println!("loops {:?}, depth {}", self.vm.loops(), self.vm.depth()),
+            "set" => self.set(rest)?,
+            "push" if !rest.is_empty() => self.vm.stack.push_back(value_of(rest)?),
+            "push" => return Err("Usage: push V".to_string()),
+            "pop" => {
+                self.vm.stack.pop_back();
+            }
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("Unknown command `{}`, try help", command)),
+        }
+        Ok(true)
+    }
+}
+
+/// The value a literal stands for: an integer, a character or a string.
+fn value_of(text: &str) -> Result<Value, String> {
+    let not_a_value = || format!("`{}` is not a value", text);
+    let mut tokens = lex(text.trim(), "<debug>");
+    let value = match tokens.next() {
+        Some(Ok(tok)) => match tok.node {
+            Token::Number(n) => Value::Int(n),
+            Token::Str(s) => Value::from(s.as_str()),
+            _ => return Err(not_a_value()),
+        },
+        Some(Err(e)) => return Err(e.kind.to_string()),
+        None => return Err(not_a_value()),
+    };
+    match tokens.next() {
+        None => Ok(value),
+        Some(_) => Err(not_a_value()),
+    }
+}
+
+/// Interactive stepping through `program`, which comes from `file`. Returns the exit status.
+pub fn run(program: &Program, mut vm: Vm, file: &str) -> i32 {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    vm.start(program);
+    let mut debugger = Debugger {
+        program,
+        vm,
+        file: file.to_string(),
+        breakpoints: BTreeSet::new(),
+        failed: false,
+    };
+    debugger.show();
+    let mut last = String::new();
+    loop {
+        let line = match editor.readline("(debug) ") {
+            Ok(line) if line.trim().is_empty() => last.clone(),
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                line
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        match debugger.command(&line) {
+            Ok(true) => {}
+            Ok(false) => return 0,
+            Err(e) => eprintln!("{}", e),
+        }
+        last = line;
+    }
+}
@@ -0,0 +1,707 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::rc::Rc;
+
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub col: usize,
+    /// Set on tokens that came out of a macro expansion.
+    pub expansion: Option<Rc<Expansion>>,
+}
+
+impl Span {
+    /// ` (in expansion of `m` at ...)` for a span from a macro expansion, else nothing.
+    pub fn expansion_note(&self) -> String {
+        match &self.expansion {
+            Some(e) => format!(" (in expansion of `{}` at {})", e.name, e.site),
+            None => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// The outermost use of a macro, by name, that a token was expanded from.
+#[derive(Clone, Debug)]
+pub struct Expansion {
+    pub name: String,
+    pub site: Span,
+}
+
+#[derive(Clone, Debug)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug)]
+pub enum Token {
+    Word(String),
+    Number(i64),
+    /// `"..."`, with escapes resolved.
+    Str(String),
+    /// `// ...` or `/* ... */`, text without the delimiters. Kept for tooling, skipped by `parse`.
+    Comment {
+        text: String,
+        block: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub enum LexErrorKind {
+    UnterminatedComment,
+    IntegerOverflow(String),
+    InvalidDigit { digit: char, radix: u32 },
+    MissingDigits(String),
+    UnterminatedChar,
+    EmptyChar,
+    MultiCharLiteral,
+    UnknownEscape(char),
+    UnterminatedString,
+    Io(String),
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnterminatedComment => write!(f, "`/*` is never closed"),
+            LexErrorKind::IntegerOverflow(lit) => {
+                write!(f, "Integer literal `{}` does not fit in 64 bits", lit)
+            }
+            LexErrorKind::InvalidDigit { digit, radix } => {
+                write!(f, "Invalid digit `{}` in base {} literal", digit, radix)
+            }
+            LexErrorKind::MissingDigits(lit) => write!(f, "Literal `{}` has no digits", lit),
+            LexErrorKind::UnterminatedChar => write!(f, "Character literal is never closed"),
+            LexErrorKind::EmptyChar => write!(f, "Empty character literal"),
+            LexErrorKind::MultiCharLiteral => {
+                write!(f, "Character literal must contain exactly one character")
+            }
+            LexErrorKind::UnknownEscape(c) => write!(f, "Unknown escape sequence `\\{}`", c),
+            LexErrorKind::UnterminatedString => write!(f, "String literal is never closed"),
+            LexErrorKind::Io(e) => write!(f, "Read failed: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.kind)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Operator spellings, longest first so the tokenizer can take the longest match.
+pub const PUNCTUATION: [&str; 25] = [
+    "}{", "<=", ">=", "==", "!=", "..", ",,", "+", "-", "*", "/", "%", "<", ">", ":", ";", "^",
+    "?", "@", "{", "}", "(", ")", "[", "]",
+];
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_newline(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+/// The character `\c` stands for in character and string literals.
+fn escape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(c),
+        _ => None,
+    }
+}
+
+fn lex_digits(lit: &str, digits: &str, radix: u32, negative: bool) -> Result<i64, LexErrorKind> {
+    let mut acc: i128 = 0;
+    let mut seen = false;
+    for c in digits.chars().filter(|c| *c != '_') {
+        let d = c
+            .to_digit(radix)
+            .ok_or(LexErrorKind::InvalidDigit { digit: c, radix })?;
+        acc = acc * radix as i128 + d as i128;
+        if acc > i64::MAX as i128 + 1 {
+            return Err(LexErrorKind::IntegerOverflow(lit.to_string()));
+        }
+        seen = true;
+    }
+    if !seen {
+        return Err(LexErrorKind::MissingDigits(lit.to_string()));
+    }
+    let value = if negative { -acc } else { acc };
+    i64::try_from(value).map_err(|_| LexErrorKind::IntegerOverflow(lit.to_string()))
+}
+
+/// Classifies a run of word characters: a numeric literal if it starts with a digit and
+/// reads as one, a plain word (`2dup`) otherwise.
+fn lex_word(word: &str) -> Result<Token, LexErrorKind> {
+    let (negative, body) = match word.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, word),
+    };
+    if !body.starts_with(|c: char| c.is_ascii_digit()) {
+        return Ok(Token::Word(word.to_string()));
+    }
+    let radix = match body.get(..2) {
+        Some("0x") | Some("0X") => 16,
+        Some("0b") | Some("0B") => 2,
+        Some("0o") | Some("0O") => 8,
+        _ => 10,
+    };
+    let digits = if radix == 10 { body } else { &body[2..] };
+    let well_formed = if radix == 10 {
+        digits.chars().all(|c| c.is_ascii_digit() || c == '_')
+    } else {
+        digits
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    if well_formed {
+        lex_digits(word, digits, radix, negative).map(Token::Number)
+    } else {
+        Ok(Token::Word(word.to_string()))
+    }
+}
+
+/// Characters of a `BufRead`, decoded one line at a time.
+pub struct ReadChars<R> {
+    reader: R,
+    buf: String,
+    pos: usize,
+}
+
+impl<R: BufRead> Iterator for ReadChars<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let c = self.buf[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(Ok(c))
+    }
+}
+
+/// Streaming tokenizer; yields tokens as they are read, so the whole input never has to be in memory.
+pub struct Lexer<I> {
+    chars: I,
+    lookahead: VecDeque<char>,
+    prev: Option<char>,
+    line: usize,
+    col: usize,
+    file: Rc<str>,
+    io_error: Option<io::Error>,
+    failed: bool,
+}
+
+pub fn lex<'a>(input: &'a str, file: &str) -> Lexer<impl Iterator<Item = io::Result<char>> + 'a> {
+    Lexer::new(input.chars().map(Ok), file)
+}
+
+pub fn lex_reader<R: BufRead>(reader: R, file: &str) -> Lexer<ReadChars<R>> {
+    let chars = ReadChars {
+        reader,
+        buf: String::new(),
+        pos: 0,
+    };
+    Lexer::new(chars, file)
+}
+
+impl<I: Iterator<Item = io::Result<char>>> Lexer<I> {
+    pub fn new(chars: I, file: &str) -> Self {
+        Lexer {
+            chars,
+            lookahead: VecDeque::new(),
+            prev: None,
+            line: 1,
+            col: 1,
+            file: Rc::from(file),
+            io_error: None,
+            failed: false,
+        }
+    }
+
+    fn peek(&mut self, n: usize) -> Option<char> {
+        while self.lookahead.len() <= n && !self.failed {
+            match self.chars.next()? {
+                Ok(c) => self.lookahead.push_back(c),
+                Err(e) => {
+                    self.io_error = Some(e);
+                    self.failed = true;
+                }
+            }
+        }
+        self.lookahead.get(n).copied()
+    }
+
+    fn starts_with(&mut self, s: &str) -> bool {
+        s.chars().enumerate().all(|(n, c)| self.peek(n) == Some(c))
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0)?;
+        self.lookahead.pop_front();
+        self.prev = Some(c);
+        // `\r\n` is a single line break, counted on the `\n`.
+        if is_newline(c) && !(c == '\r' && self.peek(0) == Some('\n')) {
+            self.line += 1;
+            self.col = 1;
+        } else if c != '\r' {
+            self.col += 1;
+        }
+        Some(c)
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            col: self.col,
+            expansion: None,
+        }
+    }
+
+    fn lex_comment(&mut self) -> Result<Token, LexErrorKind> {
+        let block = self.peek(1) == Some('*');
+        self.bump();
+        self.bump();
+        let mut text = String::new();
+        let mut depth = 1usize;
+        while let Some(c) = self.peek(0) {
+            if !block && is_newline(c) {
+                break;
+            }
+            if block && self.starts_with("/*") {
+                depth += 1;
+            } else if block && self.starts_with("*/") {
+                depth -= 1;
+                if depth == 0 {
+                    self.bump();
+                    self.bump();
+                    break;
+                }
+            }
+            if block && (self.starts_with("/*") || self.starts_with("*/")) {
+                text.extend(self.bump());
+            }
+            text.extend(self.bump());
+        }
+        if block && depth > 0 {
+            return Err(LexErrorKind::UnterminatedComment);
+        }
+        Ok(Token::Comment { text, block })
+    }
+
+    /// `'A'`, `'\n'` and friends; the token is the character's code point.
+    fn lex_char(&mut self) -> Result<Token, LexErrorKind> {
+        self.bump();
+        let c = match self.bump() {
+            None => return Err(LexErrorKind::UnterminatedChar),
+            Some(c) if is_newline(c) => return Err(LexErrorKind::UnterminatedChar),
+            Some('\'') => return Err(LexErrorKind::EmptyChar),
+            Some('\\') => match self.bump() {
+                Some(c) => match escape(c) {
+                    Some(c) => c,
+                    None => {
+                        if self.peek(0) == Some('\'') {
+                            self.bump();
+                        }
+                        return Err(LexErrorKind::UnknownEscape(c));
+                    }
+                },
+                None => return Err(LexErrorKind::UnterminatedChar),
+            },
+            Some(c) => c,
+        };
+        if self.peek(0) == Some('\'') {
+            self.bump();
+            return Ok(Token::Number(c as i64));
+        }
+        while let Some(c) = self.peek(0) {
+            if is_newline(c) {
+                break;
+            }
+            self.bump();
+            if c == '\'' {
+                return Err(LexErrorKind::MultiCharLiteral);
+            }
+        }
+        Err(LexErrorKind::UnterminatedChar)
+    }
+
+    /// `"..."` on one line, with the same escapes as character literals. An unknown escape
+    /// is reported once the closing quote is found.
+    fn lex_string(&mut self) -> Result<Token, LexErrorKind> {
+        self.bump();
+        let mut text = String::new();
+        let mut unknown = None;
+        while let Some(c) = self.peek(0) {
+            if is_newline(c) {
+                break;
+            }
+            self.bump();
+            match c {
+                '"' => {
+                    return match unknown {
+                        Some(c) => Err(LexErrorKind::UnknownEscape(c)),
+                        None => Ok(Token::Str(text)),
+                    }
+                }
+                '\\' => match self.peek(0) {
+                    Some(c) if is_newline(c) => break,
+                    Some(c) => {
+                        self.bump();
+                        match escape(c) {
+                            Some(c) => text.push(c),
+                            None => unknown = unknown.or(Some(c)),
+                        }
+                    }
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        Err(LexErrorKind::UnterminatedString)
+    }
+
+    /// Word characters, plus `.` between a word and a letter so `math.gcd` is one word.
+    fn at_word_char(&mut self) -> bool {
+        match self.peek(0) {
+            Some('.') => self.peek(1).is_some_and(|c| c.is_alphabetic() || c == '_'),
+            c => c.is_some_and(is_word_char),
+        }
+    }
+
+    fn lex_token(&mut self, c: char) -> Result<Token, LexErrorKind> {
+        let after_word = self.prev.is_some_and(is_word_char);
+        let negative = c == '-' && !after_word && self.peek(1).is_some_and(|d| d.is_ascii_digit());
+        if self.starts_with("//") || self.starts_with("/*") {
+            self.lex_comment()
+        } else if c == '\'' {
+            self.lex_char()
+        } else if c == '"' {
+            self.lex_string()
+        } else if is_word_char(c) || negative {
+            let mut word = String::new();
+            word.extend(self.bump());
+            while self.at_word_char() {
+                word.extend(self.bump());
+            }
+            lex_word(&word)
+        } else if let Some(op) = PUNCTUATION.iter().find(|op| self.starts_with(op)) {
+            op.chars().for_each(|_| {
+                self.bump();
+            });
+            Ok(Token::Word(op.to_string()))
+        } else {
+            let mut word = String::new();
+            while self
+                .peek(0)
+                .is_some_and(|c| !c.is_whitespace() && !is_word_char(c) && c != '\'' && c != '"')
+            {
+                word.extend(self.bump());
+            }
+            Ok(Token::Word(word))
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<char>>> Iterator for Lexer<I> {
+    type Item = Result<Spanned<Token>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.peek(0).is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+        let span = self.span();
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => {
+                let kind = LexErrorKind::Io(self.io_error.take()?.to_string());
+                return Some(Err(LexError { kind, span }));
+            }
+        };
+        Some(match self.lex_token(c) {
+            Ok(node) => Ok(Spanned { node, span }),
+            Err(kind) => Err(LexError { kind, span }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Result<Token, LexErrorKind>> {
+        lex(input, "<test>")
+            .map(|t| t.map(|t| t.node).map_err(|e| e.kind))
+            .collect()
+    }
+
+    fn number(input: &str) -> Result<i64, LexErrorKind> {
+        match tokens(input).as_slice() {
+            [Ok(Token::Number(n))] => Ok(*n),
+            [Err(kind)] => Err(kind.clone()),
+            other => panic!("`{}` lexed as {:?}", input, other),
+        }
+    }
+
+    fn words(input: &str) -> Vec<String> {
+        tokens(input)
+            .into_iter()
+            .map(|t| match t {
+                Ok(Token::Word(w)) => w,
+                Ok(Token::Number(n)) => n.to_string(),
+                other => panic!("`{}` lexed {:?}", input, other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn i64_limits_in_every_radix() {
+        let max = [
+            "9223372036854775807",
+            "0x7fffffffffffffff",
+            "0o777777777777777777777",
+            "0b111111111111111111111111111111111111111111111111111111111111111",
+        ];
+        for lit in max.iter() {
+            assert_eq!(number(lit).unwrap(), i64::MAX, "{}", lit);
+        }
+        let min = [
+            "-9223372036854775808",
+            "-0x8000000000000000",
+            "-0o1000000000000000000000",
+            "-0b1000000000000000000000000000000000000000000000000000000000000000",
+        ];
+        for lit in min.iter() {
+            assert_eq!(number(lit).unwrap(), i64::MIN, "{}", lit);
+        }
+    }
+
+    #[test]
+    fn literals_past_the_limits_overflow() {
+        for lit in [
+            "9223372036854775808",
+            "-9223372036854775809",
+            "0x1_0000_0000_0000_0000",
+        ]
+        .iter()
+        {
+            match number(lit) {
+                Err(LexErrorKind::IntegerOverflow(s)) => assert_eq!(&s, lit),
+                other => panic!("`{}` lexed as {:?}", lit, other),
+            }
+        }
+    }
+
+    #[test]
+    fn prefixes_need_valid_digits() {
+        assert!(matches!(number("0x"), Err(LexErrorKind::MissingDigits(s)) if s == "0x"));
+        assert!(matches!(number("0b_"), Err(LexErrorKind::MissingDigits(s)) if s == "0b_"));
+        assert!(matches!(
+            number("0b102"),
+            Err(LexErrorKind::InvalidDigit {
+                digit: '2',
+                radix: 2
+            })
+        ));
+        assert!(matches!(
+            number("0o78"),
+            Err(LexErrorKind::InvalidDigit {
+                digit: '8',
+                radix: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn underscores_separate_digits() {
+        assert_eq!(number("1_000").unwrap(), 1000);
+        assert_eq!(number("0xff_ff").unwrap(), 0xffff);
+        assert_eq!(words("_1 2dup"), ["_1", "2dup"]);
+    }
+
+    #[test]
+    fn char_literals_and_escapes() {
+        assert_eq!(number("'a'").unwrap(), 'a' as i64);
+        assert_eq!(number("'λ'").unwrap(), 'λ' as i64);
+        let escapes = [
+            ("'\\n'", '\n'),
+            ("'\\t'", '\t'),
+            ("'\\r'", '\r'),
+            ("'\\0'", '\0'),
+            ("'\\\\'", '\\'),
+            ("'\\''", '\''),
+            ("'\\\"'", '"'),
+        ];
+        for (lit, c) in escapes.iter() {
+            assert_eq!(number(lit).unwrap(), *c as i64, "{}", lit);
+        }
+    }
+
+    #[test]
+    fn bad_char_literals() {
+        assert!(matches!(
+            number("'ab'"),
+            Err(LexErrorKind::MultiCharLiteral)
+        ));
+        assert!(matches!(number("''"), Err(LexErrorKind::EmptyChar)));
+        assert!(matches!(number("'a"), Err(LexErrorKind::UnterminatedChar)));
+        assert!(matches!(
+            number("'\\q'"),
+            Err(LexErrorKind::UnknownEscape('q'))
+        ));
+    }
+
+    #[test]
+    fn string_literals_resolve_escapes() {
+        match &tokens(r#""a\n\t\r\0\\\"\'λ b""#)[..] {
+            [Ok(Token::Str(s))] => assert_eq!(s, "a\n\t\r\0\\\"'λ b"),
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(&tokens(r#""""#)[..], [Ok(Token::Str(s))] if s.is_empty()));
+    }
+
+    #[test]
+    fn bad_string_literals_are_skipped_to_their_end() {
+        match &tokens(r#""a\qb\z" 1"#)[..] {
+            [Err(LexErrorKind::UnknownEscape('q')), Ok(Token::Number(1))] => {}
+            other => panic!("{:?}", other),
+        }
+        match &tokens("\"ab\n1")[..] {
+            [Err(LexErrorKind::UnterminatedString), Ok(Token::Number(1))] => {}
+            other => panic!("{:?}", other),
+        }
+        match &tokens("\"ab\\")[..] {
+            [Err(LexErrorKind::UnterminatedString)] => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn comment(t: &Result<Token, LexErrorKind>) -> (&str, bool) {
+        match t {
+            Ok(Token::Comment { text, block }) => (text, *block),
+            other => panic!("expected a comment, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_comments_end_at_the_newline() {
+        let toks = tokens("1 // two 3 /* \n4//\r\n5");
+        assert_eq!(toks.len(), 5, "{:?}", toks);
+        assert!(matches!(toks[0], Ok(Token::Number(1))));
+        assert_eq!(comment(&toks[1]), (" two 3 /* ", false));
+        assert!(matches!(toks[2], Ok(Token::Number(4))));
+        assert_eq!(comment(&toks[3]), ("", false));
+        assert!(matches!(toks[4], Ok(Token::Number(5))));
+    }
+
+    #[test]
+    fn block_comments_nest_and_keep_their_text() {
+        let toks = tokens("1 /* a /* b */ c\n d */ 2 /**/");
+        assert_eq!(toks.len(), 4, "{:?}", toks);
+        assert_eq!(comment(&toks[1]), (" a /* b */ c\n d ", true));
+        assert!(matches!(toks[2], Ok(Token::Number(2))));
+        assert_eq!(comment(&toks[3]), ("", true));
+    }
+
+    #[test]
+    fn unclosed_block_comments_are_errors() {
+        assert!(matches!(
+            &tokens("1 /* a /* b */")[..],
+            [Ok(Token::Number(1)), Err(LexErrorKind::UnterminatedComment)]
+        ));
+        assert!(matches!(
+            &tokens("/*/")[..],
+            [Err(LexErrorKind::UnterminatedComment)]
+        ));
+    }
+
+    #[test]
+    fn minus_after_a_word_is_subtraction() {
+        assert_eq!(words("x-1"), ["x", "-", "1"]);
+        assert_eq!(words("1-2"), ["1", "-", "2"]);
+        assert_eq!(words("x -1"), ["x", "-1"]);
+        assert_eq!(words("1 - 2"), ["1", "-", "2"]);
+        assert_eq!(words("(-1)"), ["(", "-1", ")"]);
+    }
+
+    #[test]
+    fn punctuation_takes_the_longest_match() {
+        assert_eq!(words("1 2+"), ["1", "2", "+"]);
+        assert_eq!(words("5:@{"), ["5", ":", "@", "{"]);
+        assert_eq!(words("}{"), ["}{"]);
+        assert_eq!(words("} {"), ["}", "{"]);
+        assert_eq!(words("<="), ["<="]);
+        assert_eq!(words("< ="), ["<", "="]);
+        assert_eq!(words("a<=b"), ["a", "<=", "b"]);
+        assert_eq!(words("..,,^;"), ["..", ",,", "^", ";"]);
+        assert_eq!(words("}{}"), ["}{", "}"]);
+    }
+
+    fn positions(input: &str) -> Vec<(usize, usize)> {
+        lex(input, "<test>")
+            .map(|t| {
+                let span = t.unwrap().span;
+                (span.line, span.col)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crlf_is_one_line_break() {
+        assert_eq!(
+            positions("a\r\nb c\r\n\r\nd"),
+            [(1, 1), (2, 1), (2, 3), (4, 1)]
+        );
+        assert_eq!(positions("a\rb\n\rc"), [(1, 1), (2, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn unicode_whitespace_separates_tokens() {
+        assert_eq!(
+            words("1\u{a0}2\u{2028}3\u{3000}4\u{85}5"),
+            ["1", "2", "3", "4", "5"]
+        );
+        assert_eq!(
+            positions("1\u{a0}2\u{2028}3\u{3000}4"),
+            [(1, 1), (1, 3), (2, 1), (2, 3)]
+        );
+    }
+
+    #[test]
+    fn spans_count_lines_and_columns() {
+        assert_eq!(
+            positions("a  b\n  'c'\n\"d\""),
+            [(1, 1), (1, 4), (2, 3), (3, 1)]
+        );
+    }
+}
@@ -0,0 +1,4 @@
+pub mod lexer;
+pub mod parser;
+pub mod trace;
+pub mod vm;
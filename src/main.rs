@@ -1,304 +1,208 @@
 use clap::{App, Arg};
-use regex::Regex;
-use std::collections::{HashMap, VecDeque};
+use lang::lexer::lex_reader;
+use lang::parser::Parser;
+use lang::trace::{TraceFormat, TraceLevel, Tracer};
+use lang::vm::{Value, Vm};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
 
-macro_rules! collection {
-    // map-like
-    ($($k:expr => $v:expr),* $(,)?) => {{
-        use std::iter::{Iterator, IntoIterator};
-        Iterator::collect(IntoIterator::into_iter([$(($k, $v),)*]))
-    }};
-    // set-like
-    ($($v:expr),* $(,)?) => {{
-        use std::iter::{Iterator, IntoIterator};
-        Iterator::collect(IntoIterator::into_iter([$($v,)*]))
-    }};
-}
-
-#[derive(Debug)]
-enum Token {
-    Word(String),
-    Number(i64),
-}
-
-#[derive(Clone, Copy, Debug)]
-enum Intrinsic {
-    Add,
-    Sub,
-    Mult,
-    Div,
-    Mod,
-    LT,
-    GT,
-    LE,
-    GE,
-    EQ,
-    NE,
-    Dup,
-    Drop,
-    Swap,
-    Rot,
-    Over,
-}
+mod debugger;
+mod repl;
 
-#[derive(Clone, Copy, Debug)]
-enum Op {
-    Push(i64),
-    Int(Intrinsic),
-    Cond,
-    Zaloop,
-    BStart(usize, usize),
-    BElse(usize, usize),
-    BEnd(usize),
-}
-
-fn lex_token(tok: &str) -> Token {
-    let re = Regex::new(r"^-?\d{1,10}$").unwrap();
-    if re.is_match(&tok) {
-        Token::Number(tok.parse::<i64>().unwrap())
-    } else {
-        Token::Word(tok.to_string())
+fn main() {
+    let matches = App::new("lang")
+        .version("1.0")
+        .author("a66ath <pitongogi@gmail.com>")
+        .about("Simple programming language")
+        .arg(
+            Arg::new("INPUT")
+                .help("Input file; without one, start an interactive session")
+                .index(1),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Arguments for the program, after `--`")
+                .index(2)
+                .multiple_values(true)
+                .last(true),
+        )
+        .arg(
+            Arg::new("zero-uninit")
+                .long("zero-uninit")
+                .help("Read unassigned variables as 0 instead of failing"),
+        )
+        .arg(
+            Arg::new("exit-code")
+                .long("exit-code")
+                .help("Exit with the value left on top of the stack"),
+        )
+        .arg(
+            Arg::new("debug")
+                .long("debug")
+                .help("Print the compiled ops to stderr and trace at the `loops` level"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .default_missing_value("stack")
+                .possible_values(["ops", "stack", "loops"])
+                .help("Trace every step: the op, then the stack, then the loop state"),
+        )
+        .arg(
+            Arg::new("trace-format")
+                .long("trace-format")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(["text", "json"])
+                .default_value("text")
+                .help("Trace as text or as JSON lines"),
+        )
+        .arg(
+            Arg::new("trace-file")
+                .long("trace-file")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write the trace to a file instead of stderr"),
+        )
+        .arg(
+            Arg::new("memory-size")
+                .long("memory-size")
+                .value_name("BYTES")
+                .takes_value(true)
+                .help("Most heap memory `alloc` may hand out [default: 1048576]"),
+        )
+        .arg(
+            Arg::new("no-prelude")
+                .long("no-prelude")
+                .help("Do not load the standard prelude"),
+        )
+        .arg(
+            Arg::new("include")
+                .short('I')
+                .long("include")
+                .value_name("DIR")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Directory to search for imports, before those in LANG_PATH"),
+        )
+        .subcommand(
+            App::new("debug")
+                .about("Step through a program, with breakpoints")
+                .arg(Arg::new("FILE").help("Program to debug").required(true)),
+        )
+        .get_matches();
+    let mut parser = Parser::new();
+    parser.search_path = matches
+        .values_of("include")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(paths) = std::env::var_os("LANG_PATH") {
+        parser.search_path.extend(std::env::split_paths(&paths));
     }
-}
-
-fn lex(input: &str) -> VecDeque<Token> {
-    let mut res: VecDeque<Token> = VecDeque::new();
-    let mut current_token = String::new();
-    input.chars().for_each(|c| {
-        if !c.is_whitespace() {
-            current_token.push(c);
-        } else {
-            res.push_back(lex_token(&current_token));
-            current_token = String::new();
-        }
-    });
-    if !current_token.is_empty() {
-        res.push_back(lex_token(&current_token));
+    if !matches.is_present("no-prelude") {
+        parser.load_prelude();
     }
-    res
-}
-
-fn parse(input: &VecDeque<Token>) -> VecDeque<Op> {
-    let mut res = VecDeque::<Op>::new();
-    let mut idx = 0usize;
-    let ops: HashMap<String, Op> = collection! {
-        "+".to_string() => Op::Int(Intrinsic::Add),
-        "-".to_string() => Op::Int(Intrinsic::Sub),
-        "*".to_string() => Op::Int(Intrinsic::Mult),
-        "/".to_string() => Op::Int(Intrinsic::Div),
-        "%".to_string() => Op::Int(Intrinsic::Mod),
-        "<".to_string() => Op::Int(Intrinsic::LT),
-        ">".to_string() => Op::Int(Intrinsic::GT),
-        "<=".to_string() => Op::Int(Intrinsic::LE),
-        ">=".to_string() => Op::Int(Intrinsic::GE),
-        "==".to_string() => Op::Int(Intrinsic::EQ),
-        "!=".to_string() => Op::Int(Intrinsic::NE),
-        ":".to_string() => Op::Int(Intrinsic::Dup),
-        ";".to_string() => Op::Int(Intrinsic::Drop),
-        "..".to_string() => Op::Int(Intrinsic::Swap),
-        ",,".to_string() => Op::Int(Intrinsic::Rot),
-        "^".to_string() => Op::Int(Intrinsic::Over),
-        "?".to_string() => Op::Cond,
-        "@".to_string() => Op::Zaloop,
-        "{".to_string() => Op::BStart(0, 0),
-        "}{".to_string() => Op::BElse(0, 0),
-        "}".to_string() => Op::BEnd(0)
-    };
-    let mut stack = VecDeque::<usize>::new();
-    for tok in input.iter() {
-        match tok {
-            Token::Number(n) => {
-                res.push_back(Op::Push(*n));
-                idx += 1;
+    let mut vm = Vm::new();
+    vm.zero_uninitialized = matches.is_present("zero-uninit");
+    if let Some(size) = matches.value_of("memory-size") {
+        vm.memory_size = match size.parse() {
+            Ok(size) => size,
+            Err(_) => {
+                eprintln!("--memory-size: `{}` is not a number of bytes", size);
+                std::process::exit(1);
             }
-            Token::Word(w) => {
-                if w.is_empty() {
-                    continue;
-                }
-                let op = ops.get(w).unwrap();
-                match op {
-                    Op::BStart(_, _) => {
-                        stack.push_back(idx);
-                        res.push_back(*op)
-                    }
-                    Op::BElse(_, _) => {
-                        let bi = stack.pop_back().unwrap();
-                        res[bi] = Op::BStart(idx, 0);
-                        stack.push_back(idx);
-                        res.push_back(Op::BElse(bi, 0))
-                    }
-                    Op::BEnd(_) => {
-                        let bi = stack.pop_back().unwrap();
-                        if let Op::BElse(o, _) = res[bi] {
-                            res[bi] = Op::BElse(o, idx);
-                            res[o] = Op::BStart(bi, idx);
-                            res.push_back(Op::BEnd(bi))
-                        }
-                        if let Op::BStart(_, _) = res[bi] {
-                            res[bi] = Op::BStart(bi, idx);
-                            res.push_back(Op::BEnd(bi))
-                        }
-                    }
-                    _ => res.push_back(*op),
+        };
+    }
+    let debug = matches.is_present("debug");
+    let level = match matches.value_of("trace") {
+        Some("ops") => Some(TraceLevel::Ops),
+        Some("stack") => Some(TraceLevel::Stack),
+        Some(_) => Some(TraceLevel::Loops),
+        None if debug => Some(TraceLevel::Loops),
+        None => None,
+    };
+    if let Some(level) = level {
+        let format = match matches.value_of("trace-format") {
+            Some("json") => TraceFormat::Json,
+            _ => TraceFormat::Text,
+        };
+        let out: Box<dyn Write> = match matches.value_of("trace-file") {
+            Some(path) => match File::create(path) {
+                Ok(file) => Box::new(BufWriter::new(file)),
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(1);
                 }
-                idx += 1;
+            },
+            None => Box::new(io::stderr()),
+        };
+        vm.trace = Some(Tracer::new(level, format, out));
+    }
+    let debugged = matches.subcommand_matches("debug");
+    let i = match debugged {
+        Some(debugged) => debugged.value_of("FILE"),
+        None => matches.value_of("INPUT"),
+    };
+    vm.args = std::iter::once(i.unwrap_or("<repl>"))
+        .chain(matches.values_of("ARGS").into_iter().flatten())
+        .map(String::from)
+        .collect();
+    let i = match i {
+        Some(i) => i,
+        None => std::process::exit(repl::run(parser, vm)),
+    };
+    let file = match File::open(i) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("{}: {}", i, e);
+            std::process::exit(1);
+        }
+    };
+    lex_reader(BufReader::new(file), i).for_each(|tok| parser.feed(tok));
+    let program = match parser.finish() {
+        Ok(ops) => ops,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
             }
+            std::process::exit(1);
         }
+    };
+    if debug {
+        let ops: Vec<_> = program.ops.iter().map(|op| op.node).collect();
+        eprintln!("{:?}", ops);
     }
-    res
-}
-
-fn compute(ops: VecDeque<Op>) -> Result<VecDeque<i64>, String> {
-    let mut stack = VecDeque::<i64>::new();
-    let mut idx = 0usize;
-    let mut curr_loop = VecDeque::<usize>::new();
-    while idx < ops.len() {
-        let op = ops[idx];
-        println!("{:?} {:?} {:?}", stack, op, curr_loop);
-        match op {
-            Op::Push(n) => stack.push_back(n),
-            Op::Cond => {
-                let the_thing = stack.pop_back().expect("Zero things to condition!");
-                if the_thing != 0 {
-                    stack.push_back(1);
-                }else{
-                    stack.push_back(0);
-                }
-            }
-            Op::BStart(el, en) => {
-                let cond = stack.pop_back().expect("Condition is nonexistant!");
-                if cond == 0 {
-                    idx = if el == idx { en } else { el };
-                    curr_loop.pop_back();
-                }
-            }
-            Op::BElse(_, en) => {
-                idx = en;
-            }
-            Op::BEnd(_) => {
-                if let Some(lidx) = curr_loop.back() {
-                   idx = lidx - 1
-                }
-            }
-            Op::Zaloop => {
-                let the_thing = stack.pop_back().expect("Nothing to loop around!");
-                if the_thing != 0 {
-                    stack.push_back(1);
-                }else{
-                    stack.push_back(0);
-                }
-                if let Some(lidx) = curr_loop.back() {
-                    if *lidx != idx {
-                        curr_loop.push_back(idx);
-                    }
-                }else{
-                    curr_loop.push_back(idx);
-                }
+    if debugged.is_some() {
+        std::process::exit(debugger::run(&program, vm, i));
+    }
+    if let Err(e) = vm.run(&program) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(code) = vm.exit_code {
+        std::process::exit(code as i32);
+    }
+    if matches.is_present("exit-code") {
+        match vm.stack.back() {
+            Some(Value::Int(code)) => std::process::exit(*code as i32),
+            Some(v) => {
+                eprintln!("{}: --exit-code: {:?} is not an integer", i, v);
+                std::process::exit(1);
             }
-            Op::Int(i) => {
-                let mut pop2 = || {
-                    let a = stack.pop_back().expect("Even less parameters");
-                    let b = stack.pop_back().expect("Too little parameters");
-                    (a, b)
-                };
-                match i {
-                    Intrinsic::Add => {
-                        let (a,b) = pop2();
-                        stack.push_back(a + b);
-                    }
-                    Intrinsic::Mult => {
-                        let (a,b) = pop2();
-                        stack.push_back(a * b);
-                    }
-                    Intrinsic::Sub => {
-                        let (a,b) = pop2();
-                        stack.push_back(a - b);
-                    }
-                    Intrinsic::Div => {
-                        let (a,b) = pop2();
-                        stack.push_back(a / b);
-                    }
-                    Intrinsic::Mod => {
-                        let (a,b) = pop2();
-                        stack.push_back(a % b);
-                    }
-                    Intrinsic::LT => {
-                        let (a,b) = pop2();
-                        stack.push_back((a < b) as i64);
-                    }
-                    Intrinsic::GT => {
-                        let (a,b) = pop2();
-                        stack.push_back((a > b) as i64);
-                    }
-                    Intrinsic::LE => {
-                        let (a,b) = pop2();
-                        stack.push_back((a <= b) as i64);
-                    }
-                    Intrinsic::GE => {
-                        let (a,b) = pop2();
-                        stack.push_back((a >= b) as i64);
-                    }
-                    Intrinsic::EQ => {
-                        let (a,b) = pop2();
-                        stack.push_back((a == b) as i64);
-                    }
-                    Intrinsic::NE => {
-                        let (a,b) = pop2();
-                        stack.push_back((a != b) as i64);
-                    }
-                    Intrinsic::Dup => {
-                        stack.push_back(*stack.back().expect("Nothing to dup!"));
-                    }
-                    Intrinsic::Drop => {
-                        if stack.len() < 1 {
-                            panic!("Stack is too small to die!");
-                        }
-                        stack.pop_back();
-                    }
-                    Intrinsic::Swap => {
-                        let (a, b) = pop2();
-                        stack.push_back(a);
-                        stack.push_back(b);
-                    }
-                    Intrinsic::Over => {
-                        let (a, b) = pop2();
-                        stack.push_back(b);
-                        stack.push_back(a);
-                        stack.push_back(b);
-                    }
-                    Intrinsic::Rot => {
-                        let (a, b) = pop2();
-                        let c = stack.pop_back().expect("Over_loaded!");
-                        stack.push_back(b);
-                        stack.push_back(a);
-                        stack.push_back(c);
-                    }
-                }
+            None => {
+                eprintln!("{}: --exit-code: the stack is empty", i);
+                std::process::exit(1);
             }
         }
-        idx += 1;
     }
-    println!("{:?} {:?}", stack, curr_loop);
-    Ok(stack)
-}
-
-fn main() {
-    let matches = App::new("lang")
-        .version("1.0")
-        .author("a66ath <pitongogi@gmail.com>")
-        .about("Simple programming language")
-        .arg(
-            Arg::new("INPUT")
-                .help("Input file")
-                .required(true)
-                .index(1),
-        )
-        .get_matches();
-    if let Some(i) = matches.value_of("INPUT") {
-        let content = std::fs::read_to_string(i).unwrap();
-        let res = lex(&content);
-        let res2 = parse(&res);
-        println!("{:?}", parse(&res));
-        println!("{:?}", compute(res2));
+    if !vm.stack.is_empty() {
+        println!("{:?}", vm.stack);
     }
 }
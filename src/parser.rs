@@ -0,0 +1,1515 @@
+use crate::lexer::{lex, lex_reader, Expansion, LexError, LexErrorKind, Span, Spanned, Token};
+use crate::vm::{RuntimeErrorKind, Value, Vm};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+macro_rules! collection {
+    // map-like
+    ($($k:expr => $v:expr),* $(,)?) => {{
+        use std::iter::{Iterator, IntoIterator};
+        Iterator::collect(IntoIterator::into_iter([$(($k, $v),)*]))
+    }};
+    // set-like
+    ($($v:expr),* $(,)?) => {{
+        use std::iter::{Iterator, IntoIterator};
+        Iterator::collect(IntoIterator::into_iter([$($v,)*]))
+    }};
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Intrinsic {
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Mod,
+    LT,
+    GT,
+    LE,
+    GE,
+    EQ,
+    NE,
+    Dup,
+    Drop,
+    Swap,
+    Rot,
+    Over,
+    StrLen,
+    StrCat,
+    StrCmp,
+    Substr,
+    CharAt,
+    ToNumber,
+    ToString,
+    /// Collect `v1 .. vn n` into a list.
+    List,
+    ListLen,
+    ListGet,
+    ListSet,
+    ListPush,
+    ListPop,
+    ListSlice,
+    ListCat,
+}
+
+impl Intrinsic {
+    pub fn arity(&self) -> usize {
+        match self {
+            Intrinsic::Dup | Intrinsic::Drop => 1,
+            Intrinsic::StrLen | Intrinsic::ToNumber | Intrinsic::ToString => 1,
+            Intrinsic::ListLen | Intrinsic::ListPop => 1,
+            // The items under the count are checked when the list is built.
+            Intrinsic::List => 1,
+            Intrinsic::Rot | Intrinsic::Substr | Intrinsic::ListSet | Intrinsic::ListSlice => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Builtins that talk to the world outside the stack.
+#[derive(Clone, Copy, Debug)]
+pub enum Syscall {
+    Print,
+    Emit,
+    Newline,
+    PrintStack,
+    ReadInt,
+    ReadChar,
+    ReadLine,
+    Fopen,
+    Fread,
+    Fwrite,
+    Fclose,
+    Argc,
+    Argv,
+    Getenv,
+    Exit,
+    Alloc,
+    /// Load or store the given number of bytes, little-endian. Loads zero-extend, so
+    /// `-1 a store8 a load8` gives 255.
+    Load(usize),
+    Store(usize),
+}
+
+impl Syscall {
+    pub fn arity(&self) -> usize {
+        match self {
+            Syscall::Print | Syscall::Emit => 1,
+            Syscall::Newline | Syscall::PrintStack => 0,
+            Syscall::ReadInt | Syscall::ReadChar | Syscall::ReadLine => 0,
+            Syscall::Argc => 0,
+            Syscall::Fclose | Syscall::Argv | Syscall::Getenv | Syscall::Exit => 1,
+            Syscall::Alloc | Syscall::Load(_) => 1,
+            Syscall::Store(_) => 2,
+            Syscall::Fopen | Syscall::Fread | Syscall::Fwrite => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Push(i64),
+    /// Push an entry of the program's constant pool.
+    PushConst(usize),
+    Int(Intrinsic),
+    Cond,
+    Zaloop,
+    BStart(usize, usize),
+    BElse(usize, usize),
+    BEnd(usize),
+    /// Enter a defined word; holds the word's id until the program is linked, its address after.
+    Call(usize),
+    Ret,
+    /// Move the top n values into new locals, keeping their order.
+    Bind(usize),
+    /// Drop the innermost n locals at the end of their `let`.
+    Unbind(usize),
+    /// Push a local, numbered from the start of the current word's locals.
+    Local(usize),
+    SetLocal(usize),
+    /// Push a global variable, by slot.
+    Fetch(usize),
+    Store(usize),
+    Sys(Syscall),
+    /// Call the quotation on top of the stack, or push the items of a list.
+    CallValue,
+}
+
+impl Op {
+    /// Number of stack values the op consumes.
+    pub fn arity(&self) -> usize {
+        match self {
+            Op::Push(_)
+            | Op::PushConst(_)
+            | Op::BElse(_, _)
+            | Op::BEnd(_)
+            | Op::Call(_)
+            | Op::Ret => 0,
+            Op::Unbind(_) | Op::Local(_) | Op::Fetch(_) => 0,
+            Op::SetLocal(_) | Op::Store(_) | Op::CallValue => 1,
+            Op::Bind(n) => *n,
+            Op::Cond | Op::Zaloop | Op::BStart(_, _) => 1,
+            Op::Int(i) => i.arity(),
+            Op::Sys(sys) => sys.arity(),
+        }
+    }
+
+    /// Moves the op to `offset` within the linked program, resolving calls through `addrs`.
+    fn link(self, offset: usize, addrs: &[usize]) -> Op {
+        match self {
+            Op::BStart(el, en) => Op::BStart(el + offset, en + offset),
+            Op::BElse(bi, en) => Op::BElse(bi + offset, en + offset),
+            Op::BEnd(bi) => Op::BEnd(bi + offset),
+            Op::Call(word) => Op::Call(addrs[word]),
+            op => op,
+        }
+    }
+}
+
+/// Linked code: word bodies first, then the top-level code that starts at `entry`.
+/// `globals` names the variable slots used by `Fetch` and `Store`, and `constants` holds
+/// the values pushed by `PushConst`.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    pub ops: VecDeque<Spanned<Op>>,
+    pub entry: usize,
+    pub globals: Vec<String>,
+    pub constants: Vec<Value>,
+}
+
+#[derive(Clone, Debug)]
+pub enum ParseErrorKind {
+    Lex(LexErrorKind),
+    UnknownWord {
+        word: String,
+        suggestions: Vec<String>,
+    },
+    UnmatchedElse,
+    UnmatchedEnd,
+    UnclosedBlock,
+    DuplicateElse {
+        first: Span,
+    },
+    ExpectedName(&'static str),
+    ReservedName(String),
+    Redefinition {
+        name: String,
+        first: Span,
+    },
+    NestedDefinition,
+    StrayEnd,
+    StrayIn,
+    UnclosedDefinition(String),
+    DuplicateLocal(String),
+    LetAcrossBlock {
+        open: Span,
+    },
+    UnclosedLet,
+    NotAssignable(String),
+    NotConstant(String),
+    ConstEval {
+        name: String,
+        error: RuntimeErrorKind,
+    },
+    ConstArity {
+        name: String,
+        count: usize,
+    },
+    DuplicateParam(String),
+    StrayParen(String),
+    InQuotation(String),
+    LocalInQuotation(String),
+    StrayBracket,
+    UnclosedList,
+    MacroArgs {
+        name: String,
+        expected: usize,
+        found: usize,
+        def: Span,
+    },
+    MacroDepth {
+        name: String,
+        def: Span,
+    },
+    ExpectedPath,
+    ImportInDefinition,
+    ImportNotFound(String),
+    ImportCycle(String),
+    ImportFailed {
+        path: String,
+        error: String,
+    },
+    ModuleClash {
+        module: String,
+        first: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.span)?;
+        self.fmt_kind(f)?;
+        write!(f, "{}", self.span.expansion_note())
+    }
+}
+
+impl ParseError {
+    fn fmt_kind(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::Lex(kind) => write!(f, "{}", kind),
+            ParseErrorKind::UnknownWord { word, suggestions } => {
+                write!(f, "Unknown word `{}`", word)?;
+                if !suggestions.is_empty() {
+                    let quoted: Vec<String> =
+                        suggestions.iter().map(|s| format!("`{}`", s)).collect();
+                    write!(f, ", did you mean {}?", quoted.join(" or "))?;
+                }
+                Ok(())
+            }
+            ParseErrorKind::UnmatchedElse => write!(f, "`}}{{` without a block to continue"),
+            ParseErrorKind::UnmatchedEnd => write!(f, "`}}` without a block to close"),
+            ParseErrorKind::UnclosedBlock => write!(f, "`{{` is never closed"),
+            ParseErrorKind::DuplicateElse { first } => {
+                write!(f, "Block already has a `}}{{` at {}", first)
+            }
+            ParseErrorKind::ExpectedName(after) => write!(f, "Expected a name after `{}`", after),
+            ParseErrorKind::ReservedName(name) => {
+                write!(f, "`{}` is a built-in and cannot be redefined", name)
+            }
+            ParseErrorKind::Redefinition { name, first } => {
+                write!(f, "`{}` is already defined at {}", name, first)
+            }
+            ParseErrorKind::NestedDefinition => {
+                write!(f, "`def` or `const` inside a definition")
+            }
+            ParseErrorKind::StrayEnd => write!(f, "`end` without a definition or `let` to close"),
+            ParseErrorKind::StrayIn => write!(f, "`in` without a `let`"),
+            ParseErrorKind::UnclosedDefinition(name) => {
+                write!(f, "Definition of `{}` is never closed with `end`", name)
+            }
+            ParseErrorKind::DuplicateLocal(name) => {
+                write!(f, "`{}` is bound twice in the same `let`", name)
+            }
+            ParseErrorKind::LetAcrossBlock { open } => {
+                write!(f, "Block closed while the `let` at {} is still open", open)
+            }
+            ParseErrorKind::UnclosedLet => write!(f, "`let` is never closed with `end`"),
+            ParseErrorKind::NotAssignable(name) => {
+                write!(
+                    f,
+                    "`{}` is not a variable or local and cannot be assigned",
+                    name
+                )
+            }
+            ParseErrorKind::NotConstant(name) => write!(
+                f,
+                "Only literals, constants and stack built-ins may appear in constant `{}`",
+                name
+            ),
+            ParseErrorKind::ConstEval { name, error } => {
+                write!(f, "Evaluating constant `{}` failed: {}", name, error)
+            }
+            ParseErrorKind::ConstArity { name, count } => write!(
+                f,
+                "Constant `{}` must leave exactly one value, it leaves {}",
+                name, count
+            ),
+            ParseErrorKind::DuplicateParam(name) => {
+                write!(f, "Macro parameter `{}` is declared twice", name)
+            }
+            ParseErrorKind::StrayParen(paren) => {
+                write!(f, "`{}` is only allowed around macro arguments", paren)
+            }
+            ParseErrorKind::InQuotation(word) => write!(f, "`{}` inside a quotation", word),
+            ParseErrorKind::LocalInQuotation(name) => write!(
+                f,
+                "A quotation cannot use `{}`, a local of the code around it; pass it on the stack",
+                name
+            ),
+            ParseErrorKind::StrayBracket => write!(f, "`]` without a `[` to close"),
+            ParseErrorKind::UnclosedList => write!(f, "`[` is never closed with `]`"),
+            ParseErrorKind::MacroArgs {
+                name,
+                expected,
+                found,
+                def,
+            } => write!(
+                f,
+                "Macro `{}` (defined at {}) expects {} argument(s), input ended after {}",
+                name, def, expected, found
+            ),
+            ParseErrorKind::MacroDepth { name, def } => write!(
+                f,
+                "Expanding macro `{}` (defined at {}) nests deeper than {} levels",
+                name, def, MAX_MACRO_DEPTH
+            ),
+            ParseErrorKind::ExpectedPath => write!(f, "Expected a quoted path after `import`"),
+            ParseErrorKind::ImportInDefinition => write!(f, "`import` inside a definition"),
+            ParseErrorKind::ImportNotFound(path) => write!(
+                f,
+                "Cannot find `{}` next to the importing file or on the search path",
+                path
+            ),
+            ParseErrorKind::ImportCycle(path) => {
+                write!(f, "Importing `{}` again while it is being imported", path)
+            }
+            ParseErrorKind::ImportFailed { path, error } => {
+                write!(f, "Cannot read `{}`: {}", path, error)
+            }
+            ParseErrorKind::ModuleClash { module, first } => write!(
+                f,
+                "Module `{}` is already imported from `{}`",
+                module, first
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Optimal string alignment distance, so that swapped characters (`=<` for `<=`) count as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Up to three known words closest to `word`, nearest first.
+fn suggest<'a>(word: &str, known: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let limit = (word.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &String)> = known
+        .map(|k| (edit_distance(word, k), k))
+        .filter(|(d, _)| *d <= limit && *d < word.chars().count())
+        .collect();
+    close.sort();
+    close.into_iter().take(3).map(|(_, k)| k.clone()).collect()
+}
+
+const KEYWORDS: [&str; 9] = [
+    "def", "end", "let", "in", "var", "to", "const", "macro", "import",
+];
+
+/// Keywords whose construct is closed by `end`.
+const OPENERS: [&str; 4] = ["def", "const", "let", "macro"];
+
+/// Source of the standard words compiled ahead of user code.
+pub const PRELUDE: &str = include_str!("prelude.lang");
+
+/// Module the prelude is compiled in. No file stem looks like it, so user code can only
+/// reach prelude names through the fallback in `resolve` and may define its own.
+const PRELUDE_MODULE: &str = "<prelude>";
+
+fn prelude_name(name: &str) -> String {
+    format!("{}.{}", PRELUDE_MODULE, name)
+}
+
+/// `name` as user code spells it.
+fn visible_name(name: &str) -> &str {
+    name.strip_prefix(PRELUDE_MODULE)
+        .and_then(|name| name.strip_prefix('.'))
+        .unwrap_or(name)
+}
+
+/// How deeply macro expansions may nest before expansion is abandoned.
+pub const MAX_MACRO_DEPTH: usize = 64;
+
+/// A `let` scope; `blocks` is how many blocks were open when it started.
+#[derive(Clone)]
+struct Let {
+    names: Vec<String>,
+    span: Span,
+    blocks: usize,
+}
+
+/// Code under construction, with the indices of its still-open blocks and its `let` scopes.
+#[derive(Clone, Default)]
+struct Code {
+    ops: VecDeque<Spanned<Op>>,
+    blocks: VecDeque<usize>,
+    lets: Vec<Let>,
+}
+
+#[derive(Clone)]
+struct Word {
+    name: String,
+    def: Option<Span>,
+    body: Option<VecDeque<Spanned<Op>>>,
+    uses: Vec<Span>,
+    /// Words that calls go to instead, first defined one wins, if this one never is.
+    fallbacks: Vec<String>,
+}
+
+/// A `[ ... ]` being read. It is a list literal while it holds only literals and
+/// constants; any other word makes it a quotation, compiled into `code`.
+#[derive(Clone)]
+struct Bracket {
+    span: Span,
+    items: Vec<Value>,
+    code: Option<Code>,
+}
+
+/// A `def` or `const` body being compiled; `word` is `None` for a constant.
+#[derive(Clone)]
+struct Definition {
+    name: String,
+    word: Option<usize>,
+    code: Code,
+    span: Span,
+    redefined: bool,
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Spanned<Token>>,
+    span: Span,
+}
+
+/// A `macro` being read: its parameters until `in`, then its body until the matching `end`.
+#[derive(Clone)]
+struct MacroDef {
+    name: String,
+    span: Span,
+    params: Vec<String>,
+    body: Option<Vec<Spanned<Token>>>,
+    depth: usize,
+    redefined: bool,
+}
+
+/// A macro use collecting its arguments; `group` holds a `( ... )` argument still open
+/// and its nesting depth. `span` is the use in the source, even for nested expansions.
+#[derive(Clone)]
+struct Invocation {
+    name: String,
+    span: Span,
+    depth: usize,
+    args: Vec<Vec<Spanned<Token>>>,
+    group: Option<(usize, Vec<Spanned<Token>>)>,
+}
+
+/// Single-pass compiler from tokens to a `Program`. Words may be called before they are
+/// defined; calls are resolved when the program is linked in `finish`.
+///
+/// `import "path"` is compiled in place: names defined in the imported file are prefixed
+/// with its stem (`math.gcd`), and each file is read at most once. Two different files may
+/// not share a stem.
+#[derive(Clone)]
+pub struct Parser {
+    /// Directories searched for imports not found next to the importing file.
+    pub search_path: Vec<PathBuf>,
+    /// Stem of the file being compiled, empty for the main file.
+    module: String,
+    /// File each imported module was read from, by module name.
+    modules: HashMap<String, PathBuf>,
+    /// Files whose imports are being compiled, outermost first.
+    files: Vec<PathBuf>,
+    ops: HashMap<String, Op>,
+    words: Vec<Word>,
+    word_ids: HashMap<String, usize>,
+    main: Code,
+    vars: HashMap<String, (usize, Span)>,
+    globals: Vec<String>,
+    consts: HashMap<String, (Value, Span)>,
+    /// Pool of the non-integer literals and constants used so far.
+    constants: Vec<Value>,
+    /// `[ ... ]` being read, innermost last.
+    brackets: Vec<Bracket>,
+    def: Option<Definition>,
+    macros: HashMap<String, Macro>,
+    macro_def: Option<MacroDef>,
+    invocation: Option<Invocation>,
+    /// Nesting depth of the macro expansion being fed, 0 for source tokens.
+    depth: usize,
+    /// The outermost macro use being expanded, marked on every token it expands to.
+    site: Option<Rc<Expansion>>,
+    /// Set when an expansion hits `MAX_MACRO_DEPTH`, to unwind the whole expansion.
+    expansion_failed: bool,
+    /// Keyword (`def`, `const`, `var`, `to`, `macro` or `import`) waiting for what follows it.
+    pending_name: Option<(&'static str, Span)>,
+    pending_let: Option<(Span, Vec<String>)>,
+    errors: Vec<ParseError>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        let ops: HashMap<String, Op> = collection! {
+            "+".to_string() => Op::Int(Intrinsic::Add),
+            "-".to_string() => Op::Int(Intrinsic::Sub),
+            "*".to_string() => Op::Int(Intrinsic::Mult),
+            "/".to_string() => Op::Int(Intrinsic::Div),
+            "%".to_string() => Op::Int(Intrinsic::Mod),
+            "<".to_string() => Op::Int(Intrinsic::LT),
+            ">".to_string() => Op::Int(Intrinsic::GT),
+            "<=".to_string() => Op::Int(Intrinsic::LE),
+            ">=".to_string() => Op::Int(Intrinsic::GE),
+            "==".to_string() => Op::Int(Intrinsic::EQ),
+            "!=".to_string() => Op::Int(Intrinsic::NE),
+            ":".to_string() => Op::Int(Intrinsic::Dup),
+            ";".to_string() => Op::Int(Intrinsic::Drop),
+            "..".to_string() => Op::Int(Intrinsic::Swap),
+            ",,".to_string() => Op::Int(Intrinsic::Rot),
+            "^".to_string() => Op::Int(Intrinsic::Over),
+            "str_len".to_string() => Op::Int(Intrinsic::StrLen),
+            "str_cat".to_string() => Op::Int(Intrinsic::StrCat),
+            "str_cmp".to_string() => Op::Int(Intrinsic::StrCmp),
+            "substr".to_string() => Op::Int(Intrinsic::Substr),
+            "char_at".to_string() => Op::Int(Intrinsic::CharAt),
+            "to_number".to_string() => Op::Int(Intrinsic::ToNumber),
+            "to_string".to_string() => Op::Int(Intrinsic::ToString),
+            "list".to_string() => Op::Int(Intrinsic::List),
+            "list_len".to_string() => Op::Int(Intrinsic::ListLen),
+            "list_get".to_string() => Op::Int(Intrinsic::ListGet),
+            "list_set".to_string() => Op::Int(Intrinsic::ListSet),
+            "list_push".to_string() => Op::Int(Intrinsic::ListPush),
+            "list_pop".to_string() => Op::Int(Intrinsic::ListPop),
+            "list_slice".to_string() => Op::Int(Intrinsic::ListSlice),
+            "list_cat".to_string() => Op::Int(Intrinsic::ListCat),
+            "call".to_string() => Op::CallValue,
+            "?".to_string() => Op::Cond,
+            "@".to_string() => Op::Zaloop,
+            "{".to_string() => Op::BStart(0, 0),
+            "}{".to_string() => Op::BElse(0, 0),
+            "}".to_string() => Op::BEnd(0),
+            "print".to_string() => Op::Sys(Syscall::Print),
+            "emit".to_string() => Op::Sys(Syscall::Emit),
+            "newline".to_string() => Op::Sys(Syscall::Newline),
+            "print_stack".to_string() => Op::Sys(Syscall::PrintStack),
+            "read_int".to_string() => Op::Sys(Syscall::ReadInt),
+            "read_char".to_string() => Op::Sys(Syscall::ReadChar),
+            "read_line".to_string() => Op::Sys(Syscall::ReadLine),
+            "fopen".to_string() => Op::Sys(Syscall::Fopen),
+            "fread".to_string() => Op::Sys(Syscall::Fread),
+            "fwrite".to_string() => Op::Sys(Syscall::Fwrite),
+            "fclose".to_string() => Op::Sys(Syscall::Fclose),
+            "argc".to_string() => Op::Sys(Syscall::Argc),
+            "argv".to_string() => Op::Sys(Syscall::Argv),
+            "getenv".to_string() => Op::Sys(Syscall::Getenv),
+            "exit".to_string() => Op::Sys(Syscall::Exit),
+            "alloc".to_string() => Op::Sys(Syscall::Alloc),
+            "load8".to_string() => Op::Sys(Syscall::Load(1)),
+            "load16".to_string() => Op::Sys(Syscall::Load(2)),
+            "load32".to_string() => Op::Sys(Syscall::Load(4)),
+            "load64".to_string() => Op::Sys(Syscall::Load(8)),
+            "store8".to_string() => Op::Sys(Syscall::Store(1)),
+            "store16".to_string() => Op::Sys(Syscall::Store(2)),
+            "store32".to_string() => Op::Sys(Syscall::Store(4)),
+            "store64".to_string() => Op::Sys(Syscall::Store(8)),
+        };
+        Parser {
+            search_path: Vec::new(),
+            module: String::new(),
+            modules: HashMap::new(),
+            files: Vec::new(),
+            ops,
+            words: Vec::new(),
+            word_ids: HashMap::new(),
+            main: Code::default(),
+            vars: HashMap::new(),
+            globals: Vec::new(),
+            consts: HashMap::new(),
+            constants: Vec::new(),
+            brackets: Vec::new(),
+            def: None,
+            macros: HashMap::new(),
+            macro_def: None,
+            invocation: None,
+            depth: 0,
+            site: None,
+            expansion_failed: false,
+            pending_name: None,
+            pending_let: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, kind: ParseErrorKind, span: Span) {
+        self.errors.push(ParseError { kind, span });
+    }
+
+    fn code(&mut self) -> &mut Code {
+        if let Some(code) = self.brackets.iter_mut().rev().find_map(|b| b.code.as_mut()) {
+            return code;
+        }
+        match &mut self.def {
+            Some(def) => &mut def.code,
+            None => &mut self.main,
+        }
+    }
+
+    fn emit(&mut self, node: Op, span: Span) {
+        self.code().ops.push_back(Spanned { node, span });
+    }
+
+    fn word_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.word_ids.get(name) {
+            return id;
+        }
+        self.words.push(Word {
+            name: name.to_string(),
+            def: None,
+            body: None,
+            uses: Vec::new(),
+            fallbacks: Vec::new(),
+        });
+        self.word_ids.insert(name.to_string(), self.words.len() - 1);
+        self.words.len() - 1
+    }
+
+    pub fn feed(&mut self, tok: Result<Spanned<Token>, LexError>) {
+        let tok = match tok {
+            Ok(tok) => tok,
+            Err(LexError { kind, span }) => {
+                return self.error(ParseErrorKind::Lex(kind), span);
+            }
+        };
+        if let Token::Comment { .. } = tok.node {
+            return;
+        }
+        if let Some(def) = self.macro_def.take() {
+            return self.macro_token(def, tok);
+        }
+        if let Some(inv) = self.invocation.take() {
+            return self.macro_arg(inv, tok);
+        }
+        let span = tok.span;
+        if let Some((keyword, kw_span)) = self.pending_name.take() {
+            return match (keyword, tok.node) {
+                ("import", Token::Str(path)) => self.import(path, kw_span),
+                ("import", _) => self.error(ParseErrorKind::ExpectedPath, span),
+                (_, Token::Word(w)) if !KEYWORDS.contains(&w.as_str()) => match keyword {
+                    "def" => self.start_def(self.qualify(&w), kw_span, false),
+                    "const" => self.start_def(self.qualify(&w), kw_span, true),
+                    "macro" => self.start_macro(self.qualify(&w), kw_span),
+                    "var" => self.declare_var(self.qualify(&w), kw_span),
+                    _ => self.assign(w, span),
+                },
+                _ => self.error(ParseErrorKind::ExpectedName(keyword), span),
+            };
+        }
+        if let Some((let_span, mut names)) = self.pending_let.take() {
+            match &tok.node {
+                Token::Word(w) if w == "in" => return self.start_let(names, let_span),
+                Token::Word(w) if !KEYWORDS.contains(&w.as_str()) && !self.ops.contains_key(w) => {
+                    if names.contains(w) {
+                        self.error(ParseErrorKind::DuplicateLocal(w.clone()), span);
+                    } else {
+                        names.push(w.clone());
+                    }
+                    self.pending_let = Some((let_span, names));
+                    return;
+                }
+                _ => {
+                    self.error(ParseErrorKind::ExpectedName("let"), span.clone());
+                    self.start_let(names, let_span);
+                }
+            }
+        }
+        match tok.node {
+            Token::Number(n) => self.literal(Value::Int(n), span),
+            Token::Comment { .. } => {}
+            Token::Str(s) => self.literal(Value::from(s.as_str()), span),
+            // Locals shadow macros, as they do every other name.
+            Token::Word(w) if self.local(&w).is_some() => self.word(w, span),
+            Token::Word(w) => match self.resolve(&w) {
+                name if self.macros.contains_key(&name) => self.invoke(name, span),
+                _ => self.word(w, span),
+            },
+        }
+    }
+
+    /// Adds to the list literal being read, or pushes the value if there is none.
+    fn literal(&mut self, value: Value, span: Span) {
+        match self.brackets.last_mut() {
+            Some(Bracket {
+                code: None, items, ..
+            }) => items.push(value),
+            _ => self.push_value(value, span),
+        }
+    }
+
+    /// Turns the list literal being read into a quotation that pushes what it held so far.
+    fn quote(&mut self) {
+        let bracket = self.brackets.last_mut().unwrap();
+        let items = std::mem::take(&mut bracket.items);
+        let span = bracket.span.clone();
+        bracket.code = Some(Code::default());
+        for item in items {
+            self.push_value(item, span.clone());
+        }
+    }
+
+    fn close_bracket(&mut self, span: Span) {
+        let bracket = match self.brackets.pop() {
+            Some(bracket) => bracket,
+            None => return self.error(ParseErrorKind::StrayBracket, span),
+        };
+        let mut code = match bracket.code {
+            Some(code) => code,
+            None => return self.literal(Value::List(Rc::new(bracket.items)), bracket.span),
+        };
+        self.close_blocks(&code);
+        code.ops.push_back(Spanned {
+            node: Op::Ret,
+            span,
+        });
+        // Quotations are nameless words, so nothing can call them by name.
+        self.words.push(Word {
+            name: String::new(),
+            def: Some(bracket.span.clone()),
+            body: Some(code.ops),
+            uses: Vec::new(),
+            fallbacks: Vec::new(),
+        });
+        let id = self.words.len() - 1;
+        self.literal(Value::Quote(id), bracket.span);
+    }
+
+    /// Whether `name` is a local of the code around the quotation being compiled.
+    fn outer_local(&self, name: &str) -> bool {
+        let innermost = match self.brackets.iter().rposition(|b| b.code.is_some()) {
+            Some(innermost) => innermost,
+            None => return false,
+        };
+        let top = self.def.as_ref().map_or(&self.main, |def| &def.code);
+        let mut outer = self.brackets[..innermost]
+            .iter()
+            .filter_map(|b| b.code.as_ref())
+            .chain(std::iter::once(top));
+        outer.any(|code| code.lets.iter().any(|l| l.names.iter().any(|n| n == name)))
+    }
+
+    /// Integers are pushed inline, anything else from the constant pool.
+    fn push_value(&mut self, value: Value, span: Span) {
+        if let Value::Int(n) = value {
+            return self.emit(Op::Push(n), span);
+        }
+        let index = match self.constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        self.emit(Op::PushConst(index), span)
+    }
+
+    fn word(&mut self, w: String, span: Span) {
+        match w.as_str() {
+            "[" => {
+                return self.brackets.push(Bracket {
+                    span,
+                    items: Vec::new(),
+                    code: None,
+                })
+            }
+            "]" => return self.close_bracket(span),
+            _ => {}
+        }
+        if let Some(bracket) = self.brackets.last() {
+            if bracket.code.is_none() {
+                if let Some((value, _)) = self.consts.get(&self.resolve(&w)) {
+                    return self.literal(value.clone(), span);
+                }
+                self.quote();
+            }
+            let declares = matches!(w.as_str(), "def" | "const" | "var" | "macro" | "import");
+            if declares || (w == "end" && self.code().lets.is_empty()) {
+                return self.error(ParseErrorKind::InQuotation(w), span);
+            }
+        }
+        match w.as_str() {
+            "def" | "const" if self.def.is_some() => {
+                return self.error(ParseErrorKind::NestedDefinition, span);
+            }
+            "import" if self.def.is_some() => {
+                return self.error(ParseErrorKind::ImportInDefinition, span);
+            }
+            "def" | "const" | "var" | "to" | "macro" | "import" => {
+                let keyword = KEYWORDS.iter().find(|k| **k == w).unwrap();
+                self.pending_name = Some((keyword, span));
+                return;
+            }
+            "let" => {
+                self.pending_let = Some((span, Vec::new()));
+                return;
+            }
+            "end" if !self.code().lets.is_empty() => return self.end_let(span),
+            "end" => return self.end_def(span),
+            "in" => return self.error(ParseErrorKind::StrayIn, span),
+            "(" | ")" => return self.error(ParseErrorKind::StrayParen(w), span),
+            _ => {}
+        }
+        if let Some(slot) = self.local(&w) {
+            return self.emit(Op::Local(slot), span);
+        }
+        if self.outer_local(&w) {
+            return self.error(ParseErrorKind::LocalInQuotation(w), span);
+        }
+        let name = self.resolve(&w);
+        if let Some(&(slot, _)) = self.vars.get(&name) {
+            return self.emit(Op::Fetch(slot), span);
+        }
+        if let Some((value, _)) = self.consts.get(&name) {
+            return self.push_value(value.clone(), span);
+        }
+        let op = match self.ops.get(&w) {
+            Some(op) => *op,
+            None => {
+                let id = self.call_target(&w);
+                self.words[id].uses.push(span.clone());
+                return self.emit(Op::Call(id), span);
+            }
+        };
+        if let Op::BElse(_, _) | Op::BEnd(_) = op {
+            // Every `let` opened inside the block being closed ends with it.
+            loop {
+                let code = self.code();
+                let open = match code.lets.last() {
+                    Some(l) if !code.blocks.is_empty() && l.blocks >= code.blocks.len() => {
+                        l.span.clone()
+                    }
+                    _ => break,
+                };
+                self.error(ParseErrorKind::LetAcrossBlock { open }, span.clone());
+                self.end_let(span.clone());
+            }
+        }
+        let idx = self.code().ops.len();
+        match op {
+            Op::BStart(_, _) => {
+                self.code().blocks.push_back(idx);
+                self.emit(op, span)
+            }
+            Op::BElse(_, _) => {
+                let code = self.code();
+                let bi = match code.blocks.back() {
+                    Some(&bi) => bi,
+                    None => return self.error(ParseErrorKind::UnmatchedElse, span),
+                };
+                if let Op::BElse(_, _) = code.ops[bi].node {
+                    let first = code.ops[bi].span.clone();
+                    return self.error(ParseErrorKind::DuplicateElse { first }, span);
+                }
+                code.blocks.pop_back();
+                code.ops[bi].node = Op::BStart(idx, 0);
+                code.blocks.push_back(idx);
+                self.emit(Op::BElse(bi, 0), span)
+            }
+            Op::BEnd(_) => {
+                let code = self.code();
+                let bi = match code.blocks.pop_back() {
+                    Some(bi) => bi,
+                    None => return self.error(ParseErrorKind::UnmatchedEnd, span),
+                };
+                if let Op::BElse(o, _) = code.ops[bi].node {
+                    code.ops[bi].node = Op::BElse(o, idx);
+                    code.ops[o].node = Op::BStart(bi, idx);
+                } else {
+                    code.ops[bi].node = Op::BStart(bi, idx);
+                }
+                self.emit(Op::BEnd(bi), span)
+            }
+            _ => self.emit(op, span),
+        }
+    }
+
+    fn first_definition(&self, name: &str) -> Option<Span> {
+        if let Some((_, first)) = self.vars.get(name) {
+            return Some(first.clone());
+        }
+        if let Some((_, first)) = self.consts.get(name) {
+            return Some(first.clone());
+        }
+        if let Some(mac) = self.macros.get(name) {
+            return Some(mac.span.clone());
+        }
+        let id = *self.word_ids.get(name)?;
+        self.words[id].def.clone()
+    }
+
+    /// `name` as defined in the file being compiled.
+    fn qualify(&self, name: &str) -> String {
+        if self.module.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.module, name)
+        }
+    }
+
+    /// The definition `name` refers to: the current module's own, else a top-level one,
+    /// else the prelude's. Unknown names resolve to the module's, so they may be defined
+    /// later in the file.
+    fn resolve(&self, name: &str) -> String {
+        let qualified = self.qualify(name);
+        if self.first_definition(&qualified).is_some() {
+            return qualified;
+        }
+        vec![name.to_string(), prelude_name(name)]
+            .into_iter()
+            .find(|candidate| self.first_definition(candidate).is_some())
+            .unwrap_or(qualified)
+    }
+
+    /// The word a call to `w` goes to. Calls bind to the file's own name, so the file may
+    /// define the word after the call; if it never does, linking falls back to the
+    /// top-level word and then to the prelude's.
+    fn call_target(&mut self, w: &str) -> usize {
+        let qualified = self.qualify(w);
+        let id = self.word_id(&qualified);
+        if self.words[id].fallbacks.is_empty() {
+            self.words[id].fallbacks = vec![w.to_string(), prelude_name(w)]
+                .into_iter()
+                .filter(|name| *name != qualified)
+                .collect();
+        }
+        id
+    }
+
+    /// The defined word that calls to word `id` reach, if any.
+    fn target(&self, id: usize) -> Option<usize> {
+        let word = &self.words[id];
+        if word.def.is_some() {
+            return Some(id);
+        }
+        word.fallbacks
+            .iter()
+            .filter_map(|name| self.word_ids.get(name).copied())
+            .find(|&fallback| self.words[fallback].def.is_some())
+    }
+
+    /// Reports why `name` cannot be defined, if it cannot.
+    fn check_new_name(&mut self, name: &str, span: &Span) -> bool {
+        let kind = if self.ops.contains_key(name) {
+            ParseErrorKind::ReservedName(name.to_string())
+        } else if let Some(first) = self.first_definition(name) {
+            ParseErrorKind::Redefinition {
+                name: name.to_string(),
+                first,
+            }
+        } else {
+            return true;
+        };
+        self.error(kind, span.clone());
+        false
+    }
+
+    fn start_def(&mut self, name: String, span: Span, constant: bool) {
+        let redefined = !self.check_new_name(&name, &span);
+        let word = if constant {
+            None
+        } else {
+            let id = self.word_id(&name);
+            if !redefined {
+                self.words[id].def = Some(span.clone());
+            }
+            Some(id)
+        };
+        self.def = Some(Definition {
+            name,
+            word,
+            code: Code::default(),
+            span,
+            redefined,
+        });
+    }
+
+    fn declare_var(&mut self, name: String, span: Span) {
+        if !self.check_new_name(&name, &span) {
+            return;
+        }
+        self.vars.insert(name.clone(), (self.globals.len(), span));
+        self.globals.push(name);
+    }
+
+    fn start_macro(&mut self, name: String, span: Span) {
+        let redefined = !self.check_new_name(&name, &span);
+        self.macro_def = Some(MacroDef {
+            name,
+            span,
+            params: Vec::new(),
+            body: None,
+            depth: 0,
+            redefined,
+        });
+    }
+
+    /// Takes the next parameter or body token of the macro being defined.
+    fn macro_token(&mut self, mut def: MacroDef, tok: Spanned<Token>) {
+        let body = match &mut def.body {
+            Some(body) => body,
+            None => {
+                match &tok.node {
+                    Token::Word(w) if w == "in" => def.body = Some(Vec::new()),
+                    Token::Word(w) if !KEYWORDS.contains(&w.as_str()) => {
+                        if def.params.contains(w) {
+                            self.error(ParseErrorKind::DuplicateParam(w.clone()), tok.span);
+                        } else {
+                            def.params.push(w.clone());
+                        }
+                    }
+                    _ => {
+                        self.error(ParseErrorKind::ExpectedName("macro"), tok.span.clone());
+                        def.body = Some(Vec::new());
+                        return self.macro_token(def, tok);
+                    }
+                }
+                self.macro_def = Some(def);
+                return;
+            }
+        };
+        if let Token::Word(w) = &tok.node {
+            if OPENERS.contains(&w.as_str()) {
+                def.depth += 1;
+            } else if w == "end" && def.depth == 0 {
+                if !def.redefined {
+                    let mac = Macro {
+                        params: def.params,
+                        body: def.body.unwrap(),
+                        span: def.span,
+                    };
+                    self.macros.insert(def.name, mac);
+                }
+                return;
+            } else if w == "end" {
+                def.depth -= 1;
+            }
+        }
+        body.push(tok);
+        self.macro_def = Some(def);
+    }
+
+    fn invoke(&mut self, name: String, span: Span) {
+        let inv = Invocation {
+            name,
+            span: match &self.site {
+                Some(e) if self.depth > 0 => e.site.clone(),
+                _ => span,
+            },
+            depth: self.depth,
+            args: Vec::new(),
+            group: None,
+        };
+        if self.macros[&inv.name].params.is_empty() {
+            self.expand(inv);
+        } else {
+            self.invocation = Some(inv);
+        }
+    }
+
+    /// Takes the next token of a macro argument: a single token or a `( ... )` group.
+    fn macro_arg(&mut self, mut inv: Invocation, tok: Spanned<Token>) {
+        let paren = match &tok.node {
+            Token::Word(w) if w == "(" || w == ")" => Some(w == "("),
+            _ => None,
+        };
+        match (&mut inv.group, paren) {
+            (None, Some(true)) => inv.group = Some((0, Vec::new())),
+            (None, _) => inv.args.push(vec![tok]),
+            (Some((0, _)), Some(false)) => {
+                let (_, group) = inv.group.take().unwrap();
+                inv.args.push(group);
+            }
+            (Some((depth, group)), paren) => {
+                match paren {
+                    Some(true) => *depth += 1,
+                    Some(false) => *depth -= 1,
+                    None => {}
+                }
+                group.push(tok);
+            }
+        }
+        if inv.args.len() == self.macros[&inv.name].params.len() {
+            self.expand(inv);
+        } else {
+            self.invocation = Some(inv);
+        }
+    }
+
+    /// Feeds a macro's body back through the parser with its parameters replaced.
+    fn expand(&mut self, inv: Invocation) {
+        let mac = &self.macros[&inv.name];
+        if inv.depth >= MAX_MACRO_DEPTH {
+            let kind = ParseErrorKind::MacroDepth {
+                name: inv.name,
+                def: mac.span.clone(),
+            };
+            self.expansion_failed = true;
+            return self.error(kind, inv.span);
+        }
+        let mut tokens = Vec::new();
+        for tok in &mac.body {
+            match &tok.node {
+                Token::Word(w) => match mac.params.iter().position(|p| p == w) {
+                    Some(i) => tokens.extend(inv.args[i].iter().cloned()),
+                    None => tokens.push(tok.clone()),
+                },
+                _ => tokens.push(tok.clone()),
+            }
+        }
+        let outer = std::mem::replace(&mut self.depth, inv.depth + 1);
+        if outer == 0 {
+            self.site = Some(Rc::new(Expansion {
+                name: inv.name,
+                site: inv.span,
+            }));
+        }
+        for mut tok in tokens {
+            if self.expansion_failed {
+                break;
+            }
+            tok.span.expansion = self.site.clone();
+            self.feed(Ok(tok));
+        }
+        self.depth = outer;
+        if outer == 0 && self.expansion_failed {
+            self.expansion_failed = false;
+            self.invocation = None;
+        }
+    }
+
+    /// Compiles the standard prelude; call before feeding user code.
+    pub fn load_prelude(&mut self) {
+        let outer = std::mem::replace(&mut self.module, PRELUDE_MODULE.to_string());
+        lex(PRELUDE, "<prelude>").for_each(|tok| self.feed(tok));
+        self.end_of_file();
+        self.module = outer;
+    }
+
+    /// Compiles the file at `path`, relative to the importing file or a search directory,
+    /// unless it was already imported.
+    fn import(&mut self, path: String, span: Span) {
+        let importer = Path::new(&*span.file);
+        if self.files.is_empty() {
+            self.files.extend(importer.canonicalize());
+        }
+        let found = importer
+            .parent()
+            .map(|dir| dir.join(&path))
+            .into_iter()
+            .chain(self.search_path.iter().map(|dir| dir.join(&path)))
+            .find(|candidate| candidate.is_file());
+        let found = match found {
+            Some(found) => found,
+            None => return self.error(ParseErrorKind::ImportNotFound(path), span),
+        };
+        let canonical = found.canonicalize().unwrap_or_else(|_| found.clone());
+        if self.files.contains(&canonical) {
+            return self.error(ParseErrorKind::ImportCycle(path), span);
+        }
+        let module = found.file_stem().unwrap_or_default().to_string_lossy();
+        match self.modules.get(&*module) {
+            Some(first) if *first == canonical => return,
+            Some(first) => {
+                let module = module.into_owned();
+                let first = first.to_string_lossy().into_owned();
+                return self.error(ParseErrorKind::ModuleClash { module, first }, span);
+            }
+            None => {}
+        }
+        let file = match File::open(&found) {
+            Ok(file) => file,
+            Err(e) => {
+                let error = e.to_string();
+                return self.error(ParseErrorKind::ImportFailed { path, error }, span);
+            }
+        };
+        let module = module.into_owned();
+        self.modules.insert(module.clone(), canonical.clone());
+        let outer = std::mem::replace(&mut self.module, module);
+        self.files.push(canonical);
+        let name = found.to_string_lossy().into_owned();
+        lex_reader(BufReader::new(file), &name).for_each(|tok| self.feed(tok));
+        self.end_of_file();
+        self.files.pop();
+        self.module = outer;
+    }
+
+    /// `to name`: pops into a local or a global variable.
+    fn assign(&mut self, name: String, span: Span) {
+        if let Some(slot) = self.local(&name) {
+            self.emit(Op::SetLocal(slot), span)
+        } else if let Some(&(slot, _)) = self.vars.get(&self.resolve(&name)) {
+            self.emit(Op::Store(slot), span)
+        } else {
+            self.error(ParseErrorKind::NotAssignable(name), span)
+        }
+    }
+
+    fn start_let(&mut self, names: Vec<String>, span: Span) {
+        self.emit(Op::Bind(names.len()), span.clone());
+        let code = self.code();
+        let blocks = code.blocks.len();
+        code.lets.push(Let {
+            names,
+            span,
+            blocks,
+        });
+    }
+
+    fn end_let(&mut self, span: Span) {
+        let code = self.code();
+        let scope = code.lets.pop().unwrap();
+        let inner = code.blocks.split_off(scope.blocks.min(code.blocks.len()));
+        for bi in inner {
+            let span = self.code().ops[bi].span.clone();
+            self.error(ParseErrorKind::UnclosedBlock, span);
+        }
+        self.emit(Op::Unbind(scope.names.len()), span);
+    }
+
+    /// Slot of the innermost local called `name` in the code being compiled.
+    fn local(&mut self, name: &str) -> Option<usize> {
+        let code = self.code();
+        let mut base: usize = code.lets.iter().map(|l| l.names.len()).sum();
+        for scope in code.lets.iter().rev() {
+            base -= scope.names.len();
+            if let Some(i) = scope.names.iter().rposition(|n| n == name) {
+                return Some(base + i);
+            }
+        }
+        None
+    }
+
+    fn end_def(&mut self, span: Span) {
+        let mut def = match self.def.take() {
+            Some(def) => def,
+            None => return self.error(ParseErrorKind::StrayEnd, span),
+        };
+        let errors = self.errors.len();
+        self.close_blocks(&def.code);
+        match def.word {
+            Some(word) => {
+                def.code.ops.push_back(Spanned {
+                    node: Op::Ret,
+                    span,
+                });
+                if !def.redefined {
+                    self.words[word].body = Some(def.code.ops);
+                }
+            }
+            None if def.redefined || self.errors.len() > errors => {}
+            None => self.define_const(def),
+        }
+    }
+
+    /// Runs a constant's body on a fresh VM; it must leave exactly one value.
+    fn define_const(&mut self, def: Definition) {
+        // Without calls or loops the body runs each op at most once, so it always ends.
+        let impure = def.code.ops.iter().find(|op| {
+            matches!(
+                op.node,
+                Op::Call(_) | Op::CallValue | Op::Zaloop | Op::Fetch(_) | Op::Store(_) | Op::Sys(_)
+            )
+        });
+        if let Some(op) = impure {
+            let span = op.span.clone();
+            return self.error(ParseErrorKind::NotConstant(def.name), span);
+        }
+        let program = Program {
+            ops: def.code.ops,
+            constants: self.constants.clone(),
+            ..Program::default()
+        };
+        let mut vm = Vm::new();
+        let name = def.name;
+        match vm.run(&program) {
+            Err(e) => {
+                let kind = ParseErrorKind::ConstEval {
+                    name,
+                    error: e.kind,
+                };
+                self.error(kind, def.span)
+            }
+            Ok(()) if vm.stack.len() != 1 => {
+                let count = vm.stack.len();
+                self.error(ParseErrorKind::ConstArity { name, count }, def.span)
+            }
+            Ok(()) => {
+                let value = vm.stack.pop_back().unwrap();
+                self.consts.insert(name, (value, def.span));
+            }
+        }
+    }
+
+    fn close_blocks(&mut self, code: &Code) {
+        for &bi in &code.blocks {
+            let span = code.ops[bi].span.clone();
+            self.error(ParseErrorKind::UnclosedBlock, span);
+        }
+        for scope in &code.lets {
+            self.error(ParseErrorKind::UnclosedLet, scope.span.clone());
+        }
+    }
+
+    /// Reports whatever the file being compiled left open.
+    pub fn end_of_file(&mut self) {
+        if let Some(def) = self.macro_def.take() {
+            self.error(ParseErrorKind::UnclosedDefinition(def.name), def.span);
+        }
+        if let Some(inv) = self.invocation.take() {
+            let mac = &self.macros[&inv.name];
+            let kind = ParseErrorKind::MacroArgs {
+                expected: mac.params.len(),
+                found: inv.args.len(),
+                def: mac.span.clone(),
+                name: inv.name,
+            };
+            self.error(kind, inv.span);
+        }
+        match self.pending_name.take() {
+            Some(("import", span)) => self.error(ParseErrorKind::ExpectedPath, span),
+            Some((keyword, span)) => self.error(ParseErrorKind::ExpectedName(keyword), span),
+            None => {}
+        }
+        if let Some((span, names)) = self.pending_let.take() {
+            self.start_let(names, span);
+        }
+        for bracket in std::mem::take(&mut self.brackets) {
+            self.error(ParseErrorKind::UnclosedList, bracket.span);
+        }
+        if let Some(def) = self.def.take() {
+            self.close_blocks(&def.code);
+            self.error(ParseErrorKind::UnclosedDefinition(def.name), def.span);
+        }
+    }
+
+    /// Reports whatever is still open or undefined and lays the program out.
+    pub fn finish(mut self) -> Result<Program, Vec<ParseError>> {
+        self.end_of_file();
+        self.link()
+    }
+
+    /// Whether the input so far stops inside a definition, block or other construct.
+    pub fn is_incomplete(&self) -> bool {
+        self.def.is_some()
+            || self.macro_def.is_some()
+            || self.invocation.is_some()
+            || self.pending_name.is_some()
+            || self.pending_let.is_some()
+            || !self.brackets.is_empty()
+            || !self.main.blocks.is_empty()
+            || !self.main.lets.is_empty()
+    }
+
+    /// Lays out every word defined so far followed by the main code compiled since the
+    /// last call, reporting errors and undefined words. The parser keeps its definitions,
+    /// so more input can be fed and linked afterwards.
+    pub fn link(&mut self) -> Result<Program, Vec<ParseError>> {
+        let main = std::mem::take(&mut self.main);
+        self.close_blocks(&main);
+        let mut known: Vec<String> = self
+            .ops
+            .keys()
+            .chain(
+                self.words
+                    .iter()
+                    .filter(|w| w.def.is_some() && !w.name.is_empty())
+                    .map(|w| &w.name),
+            )
+            .chain(self.consts.keys())
+            .chain(self.macros.keys())
+            .map(|name| visible_name(name).to_string())
+            .collect();
+        known.sort();
+        known.dedup();
+        let targets: Vec<Option<usize>> = (0..self.words.len()).map(|id| self.target(id)).collect();
+        let mut undefined = Vec::new();
+        for (word, _) in self.words.iter().zip(&targets).filter(|(_, t)| t.is_none()) {
+            for span in &word.uses {
+                let suggestions = suggest(&word.name, known.iter());
+                let kind = ParseErrorKind::UnknownWord {
+                    word: word.name.clone(),
+                    suggestions,
+                };
+                undefined.push(ParseError {
+                    kind,
+                    span: span.clone(),
+                });
+            }
+        }
+        self.errors.extend(undefined);
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+        let mut addrs = vec![0usize; self.words.len()];
+        let mut len = 0;
+        for (id, word) in self.words.iter().enumerate() {
+            addrs[id] = len;
+            len += word.body.as_ref().map_or(0, |body| body.len());
+        }
+        for (id, target) in targets.into_iter().enumerate() {
+            addrs[id] = addrs[target.unwrap()];
+        }
+        let mut program = Program {
+            globals: self.globals.clone(),
+            constants: self
+                .constants
+                .iter()
+                .map(|c| link_value(c, &addrs))
+                .collect(),
+            ..Program::default()
+        };
+        let bodies = self.words.iter().filter_map(|w| w.body.as_ref());
+        for code in bodies.chain(std::iter::once(&main.ops)) {
+            let offset = program.ops.len();
+            program.entry = offset;
+            program.ops.extend(code.iter().map(|op| Spanned {
+                node: op.node.link(offset, &addrs),
+                span: op.span.clone(),
+            }));
+        }
+        Ok(program)
+    }
+}
+
+/// Resolves the word ids of quotations in `value` to addresses.
+fn link_value(value: &Value, addrs: &[usize]) -> Value {
+    match value {
+        Value::Quote(word) => Value::Quote(addrs[*word]),
+        Value::List(items) => Value::List(Rc::new(
+            items.iter().map(|item| link_value(item, addrs)).collect(),
+        )),
+        value => value.clone(),
+    }
+}
+
+pub fn parse<I>(tokens: I) -> Result<Program, Vec<ParseError>>
+where
+    I: IntoIterator<Item = Result<Spanned<Token>, LexError>>,
+{
+    let mut parser = Parser::new();
+    tokens.into_iter().for_each(|tok| parser.feed(tok));
+    parser.finish()
+}
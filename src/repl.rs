@@ -0,0 +1,115 @@
+use lang::lexer::{lex, lex_reader};
+use lang::parser::{Parser, Program};
+use lang::vm::Vm;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs::File;
+use std::io::BufReader;
+
+const HELP: &str = "\
+:stack      show the stack
+:clear      empty the stack
+:ops        show the ops of the last entry
+:load FILE  compile and run FILE
+:quit       leave (so does Ctrl-D)";
+
+/// What the loop does after a meta-command.
+enum Next {
+    Prompt,
+    /// Link and run what the command fed to the parser.
+    Run,
+    Quit,
+}
+
+fn command(line: &str, parser: &mut Parser, vm: &mut Vm, last: &Program) -> Next {
+    let (command, arg) = match line.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+    match command {
+        "stack" => println!("{:?}", vm.stack),
+        "clear" => vm.stack.clear(),
+        "ops" => {
+            let ops: Vec<_> = last.ops.iter().skip(last.entry).map(|op| op.node).collect();
+            println!("{:?}", ops);
+        }
+        "load" => match File::open(arg) {
+            Ok(file) => {
+                lex_reader(BufReader::new(file), arg).for_each(|tok| parser.feed(tok));
+                parser.end_of_file();
+                return Next::Run;
+            }
+            Err(e) => eprintln!("{}: {}", arg, e),
+        },
+        "quit" | "q" => return Next::Quit,
+        "help" | "h" => println!("{}", HELP),
+        _ => eprintln!("Unknown command `:{}`, try :help", command),
+    }
+    Next::Prompt
+}
+
+/// Interactive session. Each entry is compiled against the definitions made so far and run
+/// on the same stack; an entry that fails leaves both as they were. Returns the exit status.
+pub fn run(mut parser: Parser, mut vm: Vm) -> i32 {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut saved = parser.clone();
+    let mut last = Program::default();
+    let mut lines = 0;
+    loop {
+        let prompt = if parser.is_incomplete() { "... " } else { "> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                parser = saved.clone();
+                continue;
+            }
+            Err(ReadlineError::Eof) => return 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        lines += 1;
+        if !parser.is_incomplete() {
+            saved = parser.clone();
+        }
+        match line.trim().strip_prefix(':') {
+            Some(line) if !parser.is_incomplete() => {
+                match command(line, &mut parser, &mut vm, &last) {
+                    Next::Prompt => continue,
+                    Next::Run => {}
+                    Next::Quit => return 0,
+                }
+            }
+            _ => lex(&line, &format!("<repl {}>", lines)).for_each(|tok| parser.feed(tok)),
+        }
+        if parser.is_incomplete() {
+            continue;
+        }
+        let program = match parser.link() {
+            Ok(program) => program,
+            Err(errors) => {
+                errors.iter().for_each(|e| eprintln!("{}", e));
+                parser = saved.clone();
+                continue;
+            }
+        };
+        let stack = vm.stack.clone();
+        if let Err(e) = vm.run(&program) {
+            eprintln!("{}", e);
+            vm.stack = stack;
+        }
+        if let Some(code) = vm.exit_code {
+            return code as i32;
+        }
+        println!("{:?}", vm.stack);
+        last = program;
+    }
+}
@@ -0,0 +1,166 @@
+use crate::lexer::Spanned;
+use crate::parser::Op;
+use crate::vm::Value;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// How much of the VM's state each trace line shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Ops,
+    Stack,
+    Loops,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Writes a line per executed op, describing the state just before it runs.
+pub struct Tracer {
+    pub level: TraceLevel,
+    pub format: TraceFormat,
+    out: Box<dyn Write>,
+    steps: u64,
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Int(n) => n.to_string(),
+        Value::Str(s) => json_string(s),
+        Value::List(list) => json_list(list.iter().map(json_value)),
+        Value::Quote(_) => json_string(&format!("{:?}", value)),
+    }
+}
+
+fn json_list<T: ToString>(items: impl Iterator<Item = T>) -> String {
+    let items: Vec<String> = items.map(|i| i.to_string()).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl Tracer {
+    pub fn new(level: TraceLevel, format: TraceFormat, out: Box<dyn Write>) -> Self {
+        Tracer {
+            level,
+            format,
+            out,
+            steps: 0,
+        }
+    }
+
+    pub fn step(
+        &mut self,
+        index: usize,
+        op: &Spanned<Op>,
+        stack: &VecDeque<Value>,
+        loops: &VecDeque<usize>,
+    ) -> io::Result<()> {
+        let step = self.steps;
+        self.steps += 1;
+        let mut line = match self.format {
+            TraceFormat::Text => format!("{} #{} {} {:?}", step, index, op.span, op.node),
+            TraceFormat::Json => format!(
+                "{{\"step\":{},\"index\":{},\"op\":{},\"file\":{},\"line\":{},\"col\":{}",
+                step,
+                index,
+                json_string(&format!("{:?}", op.node)),
+                json_string(&op.span.file),
+                op.span.line,
+                op.span.col
+            ),
+        };
+        if self.level >= TraceLevel::Stack {
+            match self.format {
+                TraceFormat::Text => write!(line, " stack {:?}", stack),
+                TraceFormat::Json => write!(
+                    line,
+                    ",\"stack\":{}",
+                    json_list(stack.iter().map(json_value))
+                ),
+            }
+            .unwrap();
+        }
+        if self.level >= TraceLevel::Loops {
+            match self.format {
+                TraceFormat::Text => write!(line, " loops {:?}", loops),
+                TraceFormat::Json => write!(line, ",\"loops\":{}", json_list(loops.iter())),
+            }
+            .unwrap();
+        }
+        if self.format == TraceFormat::Json {
+            line.push('}');
+        }
+        writeln!(self.out, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+    use crate::vm::Vm;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines_give_the_position_as_separate_fields() {
+        let program = parse(lex("1\n  \"a\\\"b\" ;", "dir/t.lang")).unwrap();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = Vm::new();
+        vm.trace = Some(Tracer::new(
+            TraceLevel::Stack,
+            TraceFormat::Json,
+            Box::new(Shared(out.clone())),
+        ));
+        vm.run(&program).unwrap();
+        let out = String::from_utf8(out.borrow().clone()).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"step":0,"index":0,"op":"Push(1)","file":"dir/t.lang","line":1,"col":1,"stack":[]}"#,
+                r#"{"step":1,"index":1,"op":"PushConst(0)","file":"dir/t.lang","line":2,"col":3,"stack":[1]}"#,
+                r#"{"step":2,"index":2,"op":"Int(Drop)","file":"dir/t.lang","line":2,"col":10,"stack":[1,"a\"b"]}"#,
+            ]
+        );
+    }
+}
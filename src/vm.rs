@@ -0,0 +1,1422 @@
+use crate::lexer::Spanned;
+use crate::parser::{Intrinsic, Op, Program, Syscall};
+use crate::trace::Tracer;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// What a value is, for type errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Str,
+    List,
+    Quote,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "integer"),
+            Type::Str => write!(f, "string"),
+            Type::List => write!(f, "list"),
+            Type::Quote => write!(f, "quotation"),
+        }
+    }
+}
+
+/// A stack value. Strings and lists are shared, so copying one with `:` or `^` is cheap.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Str(Rc<str>),
+    List(Rc<Vec<Value>>),
+    /// `[ code ]`, by the address of its compiled body.
+    Quote(usize),
+}
+
+impl Value {
+    pub fn kind(&self) -> Type {
+        match self {
+            Value::Int(_) => Type::Int,
+            Value::Str(_) => Type::Str,
+            Value::List(_) => Type::List,
+            Value::Quote(_) => Type::Quote,
+        }
+    }
+
+    pub fn as_int(&self) -> Result<i64, RuntimeErrorKind> {
+        match self {
+            Value::Int(n) => Ok(*n),
+            v => Err(RuntimeErrorKind::TypeMismatch {
+                expected: Type::Int,
+                found: v.kind(),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&Rc<str>, RuntimeErrorKind> {
+        match self {
+            Value::Str(s) => Ok(s),
+            v => Err(RuntimeErrorKind::TypeMismatch {
+                expected: Type::Str,
+                found: v.kind(),
+            }),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&Rc<Vec<Value>>, RuntimeErrorKind> {
+        match self {
+            Value::List(list) => Ok(list),
+            v => Err(RuntimeErrorKind::TypeMismatch {
+                expected: Type::List,
+                found: v.kind(),
+            }),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(Rc::from(s))
+    }
+}
+
+/// Strings quoted, as in stack dumps: `[1, "a", [2, 3]]`.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::List(list) => f.debug_list().entries(list.iter()).finish(),
+            Value::Quote(addr) => write!(f, "<quotation #{}>", addr),
+        }
+    }
+}
+
+/// Strings as their text, as `print` writes them. Lists print as in stack dumps.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{}", s),
+            v => write!(f, "{:?}", v),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum RuntimeErrorKind {
+    StackUnderflow { required: usize, available: usize },
+    DivisionByZero,
+    ModuloByZero,
+    Overflow,
+    InvalidJump(usize),
+    CallStackOverflow(usize),
+    ReturnWithoutCall,
+    UnboundLocal(usize),
+    UninitializedVariable(String),
+    InvalidChar(i64),
+    Io(io::ErrorKind),
+    NotANumber,
+    InvalidUtf8,
+    InvalidCount(i64),
+    OutOfMemory { requested: i64, available: usize },
+    OutOfBounds { addr: i64, width: usize },
+    TypeMismatch { expected: Type, found: Type },
+    IndexOutOfRange { index: i64, len: usize },
+    EmptyList,
+}
+
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::StackUnderflow {
+                required,
+                available,
+            } => write!(
+                f,
+                "Stack underflow: needs {} value(s), has {}",
+                required, available
+            ),
+            RuntimeErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeErrorKind::ModuloByZero => write!(f, "Modulo by zero"),
+            RuntimeErrorKind::Overflow => write!(f, "Integer overflow"),
+            RuntimeErrorKind::InvalidJump(target) => write!(f, "Invalid jump to op #{}", target),
+            RuntimeErrorKind::CallStackOverflow(depth) => {
+                write!(f, "Call stack overflow: more than {} nested calls", depth)
+            }
+            RuntimeErrorKind::ReturnWithoutCall => write!(f, "Return without a matching call"),
+            RuntimeErrorKind::UnboundLocal(slot) => write!(f, "Local #{} is not bound", slot),
+            RuntimeErrorKind::UninitializedVariable(name) => {
+                write!(f, "Variable `{}` is read before it is assigned", name)
+            }
+            RuntimeErrorKind::InvalidChar(c) => write!(f, "{} is not a valid character", c),
+            RuntimeErrorKind::Io(e) => write!(f, "I/O error: {}", e),
+            RuntimeErrorKind::NotANumber => write!(f, "Input is not an integer"),
+            RuntimeErrorKind::InvalidUtf8 => write!(f, "Input is not valid UTF-8"),
+            RuntimeErrorKind::InvalidCount(n) => write!(f, "{} is not a valid count", n),
+            RuntimeErrorKind::OutOfMemory {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Cannot allocate {} byte(s), {} left",
+                requested, available
+            ),
+            RuntimeErrorKind::OutOfBounds { addr, width } => write!(
+                f,
+                "{}-byte access at address {} is outside allocated memory",
+                width, addr
+            ),
+            RuntimeErrorKind::TypeMismatch { expected, found } => {
+                write!(f, "Type mismatch: expected {}, found {}", expected, found)
+            }
+            RuntimeErrorKind::IndexOutOfRange { index, len } => {
+                write!(f, "Index {} is out of range for length {}", index, len)
+            }
+            RuntimeErrorKind::EmptyList => write!(f, "Pop from an empty list"),
+        }
+    }
+}
+
+/// Failure inside `compute`, with the op that caused it and the stack as it was before that op.
+#[derive(Clone, Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub op_index: usize,
+    pub op: Box<Spanned<Op>>,
+    pub stack: VecDeque<Value>,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} (op #{} {:?}, stack {:?}){}",
+            self.op.span,
+            self.kind,
+            self.op_index,
+            self.op.node,
+            self.stack,
+            self.op.span.expansion_note()
+        )
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// Modes for `fopen`.
+pub const MODE_READ: i64 = 0;
+pub const MODE_WRITE: i64 = 1;
+pub const MODE_APPEND: i64 = 2;
+
+/// Status codes pushed by the file words; the prelude defines them as `F_OK` and so on.
+pub const STATUS_OK: i64 = 0;
+pub const STATUS_NOT_FOUND: i64 = 1;
+pub const STATUS_PERMISSION_DENIED: i64 = 2;
+pub const STATUS_BAD_HANDLE: i64 = 3;
+pub const STATUS_INVALID: i64 = 4;
+pub const STATUS_IO: i64 = 5;
+
+fn status(e: io::Error) -> i64 {
+    match e.kind() {
+        io::ErrorKind::NotFound => STATUS_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => STATUS_PERMISSION_DENIED,
+        io::ErrorKind::InvalidInput => STATUS_INVALID,
+        _ => STATUS_IO,
+    }
+}
+
+/// Checks `index` against a string or list of `len` items.
+fn position(index: i64, len: usize) -> Result<usize, RuntimeErrorKind> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&i| i < len)
+        .ok_or(RuntimeErrorKind::IndexOutOfRange { index, len })
+}
+
+/// The items a slice of `count` from `start` takes out of `len`; it stops early at the end.
+fn slice_range(start: i64, count: i64, len: usize) -> Result<Range<usize>, RuntimeErrorKind> {
+    let start = usize::try_from(start)
+        .ok()
+        .filter(|&i| i <= len)
+        .ok_or(RuntimeErrorKind::IndexOutOfRange { index: start, len })?;
+    let count = usize::try_from(count).map_err(|_| RuntimeErrorKind::InvalidCount(count))?;
+    Ok(start..start + count.min(len - start))
+}
+
+/// Bytes `alloc` may hand out unless `Vm::memory_size` says otherwise.
+pub const DEFAULT_MEMORY_SIZE: usize = 1 << 20;
+
+/// Deepest recursion allowed before a call fails with `CallStackOverflow`.
+pub const MAX_CALL_DEPTH: usize = 100_000;
+
+/// Where a `Ret` goes back to, and the caller's loop stack and locals to restore.
+struct Frame {
+    ret: usize,
+    loops: VecDeque<usize>,
+    base: usize,
+}
+
+/// Interpreter state. Each call gets a fresh loop stack, so a word's `@ { }` never sees
+/// the caller's loops, and its locals start at `base`.
+pub struct Vm {
+    pub stack: VecDeque<Value>,
+    pub pc: usize,
+    /// Read never-assigned variables as 0 instead of failing with `UninitializedVariable`.
+    pub zero_uninitialized: bool,
+    /// Where `print`, `emit` and friends write; stdout unless replaced.
+    pub output: Box<dyn Write>,
+    /// Where `read_int`, `read_char` and `read_line` read from; stdin unless replaced.
+    pub input: Box<dyn BufRead>,
+    /// Traces every step when set.
+    pub trace: Option<Tracer>,
+    /// What `argc` and `argv` see; by convention the script's path comes first.
+    pub args: Vec<String>,
+    /// Set by `exit`, which also stops the program.
+    pub exit_code: Option<i64>,
+    /// Most bytes `alloc` may hand out in total.
+    pub memory_size: usize,
+    /// Heap; `alloc` grows it, 8-byte aligned, and addresses are offsets into it.
+    memory: Vec<u8>,
+    /// Files opened with `fopen`, by handle.
+    files: HashMap<i64, File>,
+    next_handle: i64,
+    frames: Vec<Frame>,
+    loops: VecDeque<usize>,
+    locals: Vec<Value>,
+    base: usize,
+    globals: Vec<Option<Value>>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm {
+            stack: VecDeque::new(),
+            pc: 0,
+            zero_uninitialized: false,
+            output: Box::new(io::stdout()),
+            input: Box::new(BufReader::new(io::stdin())),
+            trace: None,
+            args: Vec::new(),
+            exit_code: None,
+            memory_size: DEFAULT_MEMORY_SIZE,
+            memory: Vec::new(),
+            files: HashMap::new(),
+            next_handle: 1,
+            frames: Vec::new(),
+            loops: VecDeque::new(),
+            locals: Vec::new(),
+            base: 0,
+            globals: Vec::new(),
+        }
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn fail(&self, program: &Program, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            kind,
+            op_index: self.pc,
+            op: Box::new(program.ops[self.pc].clone()),
+            stack: self.stack.clone(),
+        }
+    }
+
+    fn jump(&self, program: &Program, target: usize) -> Result<usize, RuntimeError> {
+        if target < program.ops.len() {
+            Ok(target)
+        } else {
+            Err(self.fail(program, RuntimeErrorKind::InvalidJump(target)))
+        }
+    }
+
+    /// Points `pc` at the entry of `program`, ready for `step`. The stack and variables
+    /// carry over from earlier runs.
+    pub fn start(&mut self, program: &Program) {
+        self.pc = program.entry;
+        self.frames.clear();
+        self.loops.clear();
+        self.locals.clear();
+        self.base = 0;
+        self.globals.resize(program.globals.len(), None);
+    }
+
+    /// Whether `program` has run off its end or called `exit`.
+    pub fn is_finished(&self, program: &Program) -> bool {
+        self.pc >= program.ops.len() || self.exit_code.is_some()
+    }
+
+    /// Loop stack of the current call.
+    pub fn loops(&self) -> &VecDeque<usize> {
+        &self.loops
+    }
+
+    /// Number of calls in progress.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        self.start(program);
+        while !self.is_finished(program) {
+            if let Err(e) = self.step(program) {
+                self.flush();
+                return Err(e);
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.output.flush();
+        if let Some(trace) = &mut self.trace {
+            let _ = trace.flush();
+        }
+    }
+
+    /// Enters the code at `addr`, to return to the op after `pc`.
+    fn call(&mut self, program: &Program, addr: usize) -> Result<usize, RuntimeError> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            let kind = RuntimeErrorKind::CallStackOverflow(MAX_CALL_DEPTH);
+            return Err(self.fail(program, kind));
+        }
+        let next = self.jump(program, addr)?;
+        self.frames.push(Frame {
+            ret: self.pc + 1,
+            loops: std::mem::take(&mut self.loops),
+            base: self.base,
+        });
+        self.base = self.locals.len();
+        Ok(next)
+    }
+
+    /// Executes the op at `pc` and moves `pc` to the next one.
+    pub fn step(&mut self, program: &Program) -> Result<(), RuntimeError> {
+        let idx = self.pc;
+        let op = program.ops[idx].node;
+        if let Some(trace) = &mut self.trace {
+            let traced = trace.step(idx, &program.ops[idx], &self.stack, &self.loops);
+            if let Err(e) = traced {
+                return Err(self.fail(program, RuntimeErrorKind::Io(e.kind())));
+            }
+        }
+        if self.stack.len() < op.arity() {
+            let kind = RuntimeErrorKind::StackUnderflow {
+                required: op.arity(),
+                available: self.stack.len(),
+            };
+            return Err(self.fail(program, kind));
+        }
+        let mut next = idx + 1;
+        match op {
+            Op::Push(n) => self.stack.push_back(Value::Int(n)),
+            Op::PushConst(i) => self.stack.push_back(program.constants[i].clone()),
+            Op::Cond => {
+                let the_thing = self.pop_int().map_err(|kind| self.fail(program, kind))?;
+                self.stack.push_back(Value::Int((the_thing != 0) as i64));
+            }
+            Op::BStart(el, en) => {
+                let cond = self.pop_int().map_err(|kind| self.fail(program, kind))?;
+                if cond == 0 {
+                    next = self.jump(program, if el == idx { en } else { el })? + 1;
+                    // Only the block right after `@` belongs to the loop.
+                    if self.loops.back().map(|z| z + 1) == Some(idx) {
+                        self.loops.pop_back();
+                    }
+                }
+            }
+            Op::BElse(_, en) => {
+                next = self.jump(program, en)? + 1;
+            }
+            Op::BEnd(bi) => {
+                if let Some(&lidx) = self.loops.back() {
+                    if lidx + 1 == bi {
+                        next = self.jump(program, lidx)?;
+                    }
+                }
+            }
+            Op::Zaloop => {
+                let the_thing = self.pop_int().map_err(|kind| self.fail(program, kind))?;
+                self.stack.push_back(Value::Int((the_thing != 0) as i64));
+                if self.loops.back() != Some(&idx) {
+                    self.loops.push_back(idx);
+                }
+            }
+            Op::Call(addr) => next = self.call(program, addr)?,
+            Op::CallValue => match self.stack.back().unwrap() {
+                &Value::Quote(addr) => {
+                    next = self.call(program, addr)?;
+                    self.stack.pop_back();
+                }
+                Value::List(_) => {
+                    if let Some(Value::List(items)) = self.stack.pop_back() {
+                        self.stack.extend(items.iter().cloned());
+                    }
+                }
+                v => {
+                    let kind = RuntimeErrorKind::TypeMismatch {
+                        expected: Type::Quote,
+                        found: v.kind(),
+                    };
+                    return Err(self.fail(program, kind));
+                }
+            },
+            Op::Ret => {
+                let frame = match self.frames.pop() {
+                    Some(frame) => frame,
+                    None => return Err(self.fail(program, RuntimeErrorKind::ReturnWithoutCall)),
+                };
+                self.locals.truncate(self.base);
+                self.loops = frame.loops;
+                self.base = frame.base;
+                next = frame.ret;
+            }
+            Op::Bind(n) => {
+                let len = self.stack.len();
+                self.locals.extend(self.stack.drain(len - n..));
+            }
+            Op::Unbind(n) => {
+                let len = self.locals.len();
+                self.locals.truncate(len.saturating_sub(n).max(self.base));
+            }
+            Op::Local(slot) => match self.locals.get(self.base + slot) {
+                Some(v) => self.stack.push_back(v.clone()),
+                None => return Err(self.fail(program, RuntimeErrorKind::UnboundLocal(slot))),
+            },
+            Op::SetLocal(slot) => {
+                let v = self.stack.pop_back().unwrap();
+                match self.locals.get_mut(self.base + slot) {
+                    Some(local) => *local = v,
+                    None => {
+                        self.stack.push_back(v);
+                        return Err(self.fail(program, RuntimeErrorKind::UnboundLocal(slot)));
+                    }
+                }
+            }
+            Op::Fetch(slot) => match &self.globals[slot] {
+                Some(v) => self.stack.push_back(v.clone()),
+                None if self.zero_uninitialized => self.stack.push_back(Value::Int(0)),
+                None => {
+                    let name = program.globals[slot].clone();
+                    let kind = RuntimeErrorKind::UninitializedVariable(name);
+                    return Err(self.fail(program, kind));
+                }
+            },
+            Op::Store(slot) => self.globals[slot] = self.stack.pop_back(),
+            Op::Int(i) => {
+                if let Err(kind) = self.intrinsic(i) {
+                    return Err(self.fail(program, kind));
+                }
+            }
+            Op::Sys(sys) => {
+                if let Err(kind) = self.syscall(sys) {
+                    return Err(self.fail(program, kind));
+                }
+            }
+        }
+        self.pc = next;
+        Ok(())
+    }
+
+    /// Runs an intrinsic whose arity has been checked. Operands are read before anything is
+    /// popped, so a failing op leaves the stack as it was.
+    fn intrinsic(&mut self, i: Intrinsic) -> Result<(), RuntimeErrorKind> {
+        let stack = &mut self.stack;
+        let len = stack.len();
+        let (a, b) = match i {
+            Intrinsic::Dup => {
+                stack.push_back(stack[len - 1].clone());
+                return Ok(());
+            }
+            Intrinsic::Drop => {
+                stack.pop_back();
+                return Ok(());
+            }
+            Intrinsic::Swap => {
+                stack.swap(len - 1, len - 2);
+                return Ok(());
+            }
+            Intrinsic::Over => {
+                stack.push_back(stack[len - 2].clone());
+                return Ok(());
+            }
+            Intrinsic::Rot => {
+                let c = stack.remove(len - 3).unwrap();
+                stack.push_back(c);
+                return Ok(());
+            }
+            // Any two values compare; values of different types are never equal.
+            Intrinsic::EQ | Intrinsic::NE => {
+                let equal = stack[len - 1] == stack[len - 2];
+                stack.truncate(len - 2);
+                let res = equal == matches!(i, Intrinsic::EQ);
+                stack.push_back(Value::Int(res as i64));
+                return Ok(());
+            }
+            Intrinsic::StrLen
+            | Intrinsic::StrCat
+            | Intrinsic::StrCmp
+            | Intrinsic::Substr
+            | Intrinsic::CharAt
+            | Intrinsic::ToNumber
+            | Intrinsic::ToString => return self.string_op(i),
+            Intrinsic::List
+            | Intrinsic::ListLen
+            | Intrinsic::ListGet
+            | Intrinsic::ListSet
+            | Intrinsic::ListPush
+            | Intrinsic::ListPop
+            | Intrinsic::ListSlice
+            | Intrinsic::ListCat => return self.list_op(i),
+            _ => (stack[len - 1].as_int()?, stack[len - 2].as_int()?),
+        };
+        let res = match i {
+            Intrinsic::Add => a.checked_add(b).ok_or(RuntimeErrorKind::Overflow)?,
+            Intrinsic::Mult => a.checked_mul(b).ok_or(RuntimeErrorKind::Overflow)?,
+            Intrinsic::Sub => a.checked_sub(b).ok_or(RuntimeErrorKind::Overflow)?,
+            Intrinsic::Div if b == 0 => return Err(RuntimeErrorKind::DivisionByZero),
+            Intrinsic::Div => a.checked_div(b).ok_or(RuntimeErrorKind::Overflow)?,
+            Intrinsic::Mod if b == 0 => return Err(RuntimeErrorKind::ModuloByZero),
+            Intrinsic::Mod => a.checked_rem(b).ok_or(RuntimeErrorKind::Overflow)?,
+            Intrinsic::LT => (a < b) as i64,
+            Intrinsic::GT => (a > b) as i64,
+            Intrinsic::LE => (a <= b) as i64,
+            Intrinsic::GE => (a >= b) as i64,
+            _ => unreachable!(),
+        };
+        stack.truncate(len - 2);
+        stack.push_back(Value::Int(res));
+        Ok(())
+    }
+
+    /// String intrinsics. Unlike arithmetic these take their operands in the order they
+    /// were pushed: `"ab" "cd" str_cat` is `"abcd"`. Positions count characters.
+    fn string_op(&mut self, i: Intrinsic) -> Result<(), RuntimeErrorKind> {
+        let len = self.stack.len();
+        let top = &self.stack[len - 1];
+        let (pops, res) = match i {
+            Intrinsic::StrLen => (1, Value::Int(top.as_str()?.chars().count() as i64)),
+            Intrinsic::ToString => (1, Value::from(top.to_string().as_str())),
+            Intrinsic::ToNumber => {
+                let n = top.as_str()?.trim().parse().ok();
+                self.stack.pop_back();
+                match n {
+                    Some(n) => self.push_ints([n, 1]),
+                    None => self.push_ints([0]),
+                }
+                return Ok(());
+            }
+            Intrinsic::StrCat => {
+                let (a, b) = (self.stack[len - 2].as_str()?, top.as_str()?);
+                (2, Value::from([&**a, &**b].concat().as_str()))
+            }
+            Intrinsic::StrCmp => {
+                let (a, b) = (self.stack[len - 2].as_str()?, top.as_str()?);
+                (2, Value::Int(a.cmp(b) as i64))
+            }
+            Intrinsic::CharAt => {
+                let (s, index) = (self.stack[len - 2].as_str()?, top.as_int()?);
+                let i = position(index, s.chars().count())?;
+                (2, Value::Int(s.chars().nth(i).unwrap() as i64))
+            }
+            Intrinsic::Substr => {
+                let s = self.stack[len - 3].as_str()?;
+                let (start, count) = (self.stack[len - 2].as_int()?, top.as_int()?);
+                let range = slice_range(start, count, s.chars().count())?;
+                let sub: String = s.chars().skip(range.start).take(range.len()).collect();
+                (3, Value::from(sub.as_str()))
+            }
+            _ => unreachable!(),
+        };
+        self.stack.truncate(len - pops);
+        self.stack.push_back(res);
+        Ok(())
+    }
+
+    /// List intrinsics. Lists are values, so `list_set`, `list_push` and `list_pop` leave a
+    /// changed list in place of the old one; the copy is skipped when nothing shares it.
+    fn list_op(&mut self, i: Intrinsic) -> Result<(), RuntimeErrorKind> {
+        let len = self.stack.len();
+        match i {
+            Intrinsic::List => {
+                let n = self.int(0)?;
+                let count = usize::try_from(n).map_err(|_| RuntimeErrorKind::InvalidCount(n))?;
+                if len < count + 1 {
+                    return Err(RuntimeErrorKind::StackUnderflow {
+                        required: count + 1,
+                        available: len,
+                    });
+                }
+                self.stack.pop_back();
+                let items = self.stack.drain(len - 1 - count..).collect();
+                self.stack.push_back(Value::List(Rc::new(items)));
+            }
+            Intrinsic::ListLen => {
+                let n = self.list(0)?.len();
+                self.stack.pop_back();
+                self.push_ints([n as i64]);
+            }
+            Intrinsic::ListGet => {
+                let (list, index) = (self.list(1)?, self.int(0)?);
+                let item = list[position(index, list.len())?].clone();
+                self.stack.truncate(len - 2);
+                self.stack.push_back(item);
+            }
+            Intrinsic::ListSet => {
+                let index = self.int(1)?;
+                let i = position(index, self.list(2)?.len())?;
+                let item = self.stack.pop_back().unwrap();
+                self.stack.pop_back();
+                self.update_list(|list| list[i] = item);
+            }
+            Intrinsic::ListPush => {
+                self.list(1)?;
+                let item = self.stack.pop_back().unwrap();
+                self.update_list(|list| list.push(item));
+            }
+            Intrinsic::ListPop => {
+                if self.list(0)?.is_empty() {
+                    return Err(RuntimeErrorKind::EmptyList);
+                }
+                let mut item = None;
+                self.update_list(|list| item = list.pop());
+                self.stack.extend(item);
+            }
+            Intrinsic::ListSlice => {
+                let list = self.list(2)?;
+                let (start, count) = (self.int(1)?, self.int(0)?);
+                let sub = list[slice_range(start, count, list.len())?].to_vec();
+                self.stack.truncate(len - 3);
+                self.stack.push_back(Value::List(Rc::new(sub)));
+            }
+            Intrinsic::ListCat => {
+                let (a, b) = (self.list(1)?, self.list(0)?);
+                let joined = [&a[..], &b[..]].concat();
+                self.stack.truncate(len - 2);
+                self.stack.push_back(Value::List(Rc::new(joined)));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Applies `change` to the list on top of the stack, which has been checked to be one.
+    fn update_list(&mut self, change: impl FnOnce(&mut Vec<Value>)) {
+        if let Some(Value::List(list)) = self.stack.back_mut() {
+            change(Rc::make_mut(list));
+        }
+    }
+
+    /// The list `depth` places below the top of the stack.
+    fn list(&self, depth: usize) -> Result<&Rc<Vec<Value>>, RuntimeErrorKind> {
+        self.stack[self.stack.len() - 1 - depth].as_list()
+    }
+
+    /// The integer on top of the stack, popped; left in place if it is not an integer.
+    fn pop_int(&mut self) -> Result<i64, RuntimeErrorKind> {
+        let n = self.int(0)?;
+        self.stack.pop_back();
+        Ok(n)
+    }
+
+    /// The string `depth` places below the top of the stack.
+    fn string(&self, depth: usize) -> Result<&Rc<str>, RuntimeErrorKind> {
+        self.stack[self.stack.len() - 1 - depth].as_str()
+    }
+
+    /// The integer `depth` places below the top of the stack.
+    fn int(&self, depth: usize) -> Result<i64, RuntimeErrorKind> {
+        self.stack[self.stack.len() - 1 - depth].as_int()
+    }
+
+    /// Runs a syscall whose arity has been checked. As with intrinsics, a failing syscall
+    /// leaves the stack as it was.
+    fn syscall(&mut self, sys: Syscall) -> Result<(), RuntimeErrorKind> {
+        let io = |e: io::Error| RuntimeErrorKind::Io(e.kind());
+        match sys {
+            Syscall::Print => {
+                let v = self.stack.back().unwrap();
+                write!(self.output, "{}", v).map_err(io)?;
+                self.stack.pop_back();
+            }
+            Syscall::Emit => {
+                let n = self.int(0)?;
+                let c = u32::try_from(n)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(RuntimeErrorKind::InvalidChar(n))?;
+                write!(self.output, "{}", c).map_err(io)?;
+                self.stack.pop_back();
+            }
+            Syscall::Newline => writeln!(self.output).map_err(io)?,
+            Syscall::PrintStack => writeln!(self.output, "{:?}", self.stack).map_err(io)?,
+            Syscall::ReadInt => {
+                let _ = self.output.flush();
+                match self.read_word()? {
+                    Some(word) => {
+                        let n = word.parse().map_err(|_| RuntimeErrorKind::NotANumber)?;
+                        self.push_ints([n, 1]);
+                    }
+                    None => self.push_ints([0]),
+                }
+            }
+            Syscall::ReadChar => {
+                let _ = self.output.flush();
+                match self.read_char()? {
+                    Some(c) => self.push_ints([c as i64, 1]),
+                    None => self.push_ints([0]),
+                }
+            }
+            Syscall::ReadLine => {
+                let _ = self.output.flush();
+                let mut line = String::new();
+                match self.input.read_line(&mut line) {
+                    Ok(0) => self.push_ints([0]),
+                    Ok(_) => {
+                        let trimmed = line.strip_suffix('\n').unwrap_or(&line);
+                        let trimmed = trimmed.strip_suffix('\r').unwrap_or(trimmed);
+                        self.push_found(Some(Value::from(trimmed)));
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                        return Err(RuntimeErrorKind::InvalidUtf8)
+                    }
+                    Err(e) => return Err(io(e)),
+                }
+            }
+            Syscall::Fopen => {
+                let (path, mode) = (self.string(1)?.clone(), self.int(0)?);
+                self.stack.truncate(self.stack.len() - 2);
+                let mut options = OpenOptions::new();
+                let valid = match mode {
+                    MODE_READ => Some(options.read(true)),
+                    MODE_WRITE => Some(options.write(true).create(true).truncate(true)),
+                    MODE_APPEND => Some(options.append(true).create(true)),
+                    _ => None,
+                };
+                match valid.map(|options| options.open(&*path)) {
+                    Some(Ok(file)) => {
+                        let handle = self.next_handle;
+                        self.next_handle += 1;
+                        self.files.insert(handle, file);
+                        self.push_ints([handle, STATUS_OK]);
+                    }
+                    Some(Err(e)) => self.push_ints([0, status(e)]),
+                    None => self.push_ints([0, STATUS_INVALID]),
+                }
+            }
+            Syscall::Fread => {
+                let len = self.stack.len();
+                let (handle, max) = (self.int(1)?, self.int(0)?);
+                let max = u64::try_from(max).map_err(|_| RuntimeErrorKind::InvalidCount(max))?;
+                self.stack.truncate(len - 2);
+                let mut bytes = Vec::new();
+                let read = match self.files.get(&handle) {
+                    Some(file) => file.take(max).read_to_end(&mut bytes).map_err(status),
+                    None => Err(STATUS_BAD_HANDLE),
+                };
+                let code = read.map_or_else(|code| code, |_| STATUS_OK);
+                let bytes = bytes.into_iter().map(|b| Value::Int(b.into())).collect();
+                self.stack.push_back(Value::List(Rc::new(bytes)));
+                self.push_ints([code]);
+            }
+            Syscall::Fwrite => {
+                let handle = self.int(0)?;
+                let bytes: Option<Vec<u8>> = match &self.stack[self.stack.len() - 2] {
+                    Value::Str(s) => Some(s.as_bytes().to_vec()),
+                    Value::List(items) => items
+                        .iter()
+                        .map(|b| b.as_int().ok().and_then(|b| u8::try_from(b).ok()))
+                        .collect(),
+                    v => {
+                        return Err(RuntimeErrorKind::TypeMismatch {
+                            expected: Type::Str,
+                            found: v.kind(),
+                        })
+                    }
+                };
+                self.stack.truncate(self.stack.len() - 2);
+                let code = match (self.files.get_mut(&handle), bytes) {
+                    (None, _) => STATUS_BAD_HANDLE,
+                    (Some(_), None) => STATUS_INVALID,
+                    (Some(file), Some(bytes)) => {
+                        file.write_all(&bytes).map_or_else(status, |_| STATUS_OK)
+                    }
+                };
+                self.push_ints([code]);
+            }
+            Syscall::Argc => self.push_ints([self.args.len() as i64]),
+            Syscall::Argv => {
+                let i = self.pop_int()?;
+                let arg = usize::try_from(i)
+                    .ok()
+                    .and_then(|i| self.args.get(i))
+                    .map(|arg| Value::from(arg.as_str()));
+                self.push_found(arg);
+            }
+            Syscall::Getenv => {
+                let name = self.string(0)?.clone();
+                self.stack.pop_back();
+                let value =
+                    std::env::var_os(&*name).map(|value| Value::from(&*value.to_string_lossy()));
+                self.push_found(value);
+            }
+            Syscall::Exit => self.exit_code = Some(self.pop_int()?),
+            Syscall::Alloc => {
+                let n = self.int(0)?;
+                let addr = self.memory.len();
+                let available = self.memory_size.saturating_sub(addr);
+                let size = usize::try_from(n)
+                    .ok()
+                    .and_then(|n| n.checked_add(7))
+                    .map(|n| n / 8 * 8)
+                    .filter(|&size| size <= available)
+                    .ok_or(RuntimeErrorKind::OutOfMemory {
+                        requested: n,
+                        available,
+                    })?;
+                self.memory.resize(addr + size, 0);
+                self.stack.pop_back();
+                self.push_ints([addr as i64]);
+            }
+            Syscall::Load(width) => {
+                let addr = self.int(0)?;
+                let range = self.memory_range(addr, width)?;
+                let mut bytes = [0u8; 8];
+                bytes[..width].copy_from_slice(&self.memory[range]);
+                self.stack.pop_back();
+                self.push_ints([i64::from_le_bytes(bytes)]);
+            }
+            Syscall::Store(width) => {
+                let len = self.stack.len();
+                let (value, addr) = (self.int(1)?, self.int(0)?);
+                let range = self.memory_range(addr, width)?;
+                self.memory[range].copy_from_slice(&value.to_le_bytes()[..width]);
+                self.stack.truncate(len - 2);
+            }
+            Syscall::Fclose => {
+                let handle = self.pop_int()?;
+                let code = match self.files.remove(&handle) {
+                    Some(file) => file.sync_all().map_or_else(status, |_| STATUS_OK),
+                    None => STATUS_BAD_HANDLE,
+                };
+                self.push_ints([code]);
+            }
+        }
+        Ok(())
+    }
+
+    /// The bytes a `width`-byte access at `addr` touches, if they are all allocated.
+    fn memory_range(&self, addr: i64, width: usize) -> Result<Range<usize>, RuntimeErrorKind> {
+        usize::try_from(addr)
+            .ok()
+            .filter(|start| start.saturating_add(width) <= self.memory.len())
+            .map(|start| start..start + width)
+            .ok_or(RuntimeErrorKind::OutOfBounds { addr, width })
+    }
+
+    fn push_ints(&mut self, ints: impl IntoIterator<Item = i64>) {
+        self.stack.extend(ints.into_iter().map(Value::Int));
+    }
+
+    /// Pushes `value 1`, or `0` for `None`.
+    fn push_found(&mut self, value: Option<Value>) {
+        match value {
+            Some(value) => {
+                self.stack.push_back(value);
+                self.push_ints([1]);
+            }
+            None => self.push_ints([0]),
+        }
+    }
+
+    /// Next whitespace-separated word of input, or `None` at end of input.
+    fn read_word(&mut self) -> Result<Option<String>, RuntimeErrorKind> {
+        let mut word = Vec::new();
+        loop {
+            let buf = self
+                .input
+                .fill_buf()
+                .map_err(|e| RuntimeErrorKind::Io(e.kind()))?;
+            if buf.is_empty() {
+                break;
+            }
+            let skip = if word.is_empty() {
+                buf.iter().take_while(|b| b.is_ascii_whitespace()).count()
+            } else {
+                0
+            };
+            let taken = buf[skip..]
+                .iter()
+                .take_while(|b| !b.is_ascii_whitespace())
+                .count();
+            word.extend_from_slice(&buf[skip..skip + taken]);
+            let done = skip + taken < buf.len();
+            self.input.consume(skip + taken);
+            if done && !word.is_empty() {
+                break;
+            }
+        }
+        if word.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(word)
+            .map(Some)
+            .map_err(|_| RuntimeErrorKind::InvalidUtf8)
+    }
+
+    /// Next UTF-8 encoded character of input, or `None` at end of input.
+    fn read_char(&mut self) -> Result<Option<char>, RuntimeErrorKind> {
+        let io = |e: io::Error| RuntimeErrorKind::Io(e.kind());
+        let mut bytes = [0u8; 4];
+        if self.input.read(&mut bytes[..1]).map_err(io)? == 0 {
+            return Ok(None);
+        }
+        let len = match bytes[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(RuntimeErrorKind::InvalidUtf8),
+        };
+        self.input
+            .read_exact(&mut bytes[1..len])
+            .map_err(|_| RuntimeErrorKind::InvalidUtf8)?;
+        let s = std::str::from_utf8(&bytes[..len]).map_err(|_| RuntimeErrorKind::InvalidUtf8)?;
+        Ok(s.chars().next())
+    }
+}
+
+pub fn compute(program: &Program) -> Result<VecDeque<Value>, RuntimeError> {
+    let mut vm = Vm::new();
+    vm.run(program)?;
+    Ok(vm.stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::parser::parse;
+
+    fn run_on(mut vm: Vm, src: &str) -> Result<VecDeque<Value>, RuntimeError> {
+        let program = parse(lex(src, "<test>")).expect("program should compile");
+        vm.output = Box::new(io::sink());
+        vm.run(&program)?;
+        Ok(vm.stack)
+    }
+
+    fn fail(src: &str) -> RuntimeError {
+        run_on(Vm::new(), src).expect_err("program should fail")
+    }
+
+    fn ints(ns: &[i64]) -> VecDeque<Value> {
+        ns.iter().map(|&n| Value::Int(n)).collect()
+    }
+
+    #[test]
+    fn underflow_reports_required_and_available_depth() {
+        let cases = [
+            (";", 1, 0),
+            ("1 +", 2, 1),
+            ("1 2 ,,", 3, 2),
+            ("print", 1, 0),
+            ("1 fopen", 2, 1),
+            ("1 2 substr", 3, 2),
+        ];
+        for (src, required, available) in cases.iter() {
+            match fail(src).kind {
+                RuntimeErrorKind::StackUnderflow {
+                    required: r,
+                    available: a,
+                } => assert_eq!((r, a), (*required, *available), "{}", src),
+                other => panic!("`{}` failed with {:?}", src, other),
+            }
+        }
+    }
+
+    #[test]
+    fn division_and_modulo_by_zero() {
+        let err = fail("0 7 /");
+        assert!(matches!(err.kind, RuntimeErrorKind::DivisionByZero));
+        assert_eq!(err.stack, ints(&[0, 7]));
+        let err = fail("0 7 %");
+        assert!(matches!(err.kind, RuntimeErrorKind::ModuloByZero));
+        assert_eq!(err.stack, ints(&[0, 7]));
+    }
+
+    #[test]
+    fn min_divided_by_minus_one_overflows() {
+        for op in ["/", "%"].iter() {
+            let err = fail(&format!("-1 -9223372036854775808 {}", op));
+            assert!(matches!(err.kind, RuntimeErrorKind::Overflow), "{}", op);
+            assert_eq!(err.stack, ints(&[-1, i64::MIN]));
+        }
+        assert!(matches!(
+            fail("1 9223372036854775807 +").kind,
+            RuntimeErrorKind::Overflow
+        ));
+    }
+
+    #[test]
+    fn error_points_at_the_failing_op_with_the_stack_before_it() {
+        let src = "1 2 +\n0 5 / 9";
+        let program = parse(lex(src, "<test>")).unwrap();
+        let err = fail(src);
+        assert_eq!(err.op_index, program.entry + 5);
+        assert!(matches!(err.op.node, Op::Int(Intrinsic::Div)));
+        assert_eq!((err.op.span.line, err.op.span.col), (2, 5));
+        assert_eq!(err.stack, ints(&[3, 0, 5]));
+        assert_eq!(
+            err.to_string(),
+            "<test>:2:5: Division by zero (op #5 Int(Div), stack [3, 0, 5])"
+        );
+    }
+
+    #[test]
+    fn failing_ops_leave_the_stack_as_it_was() {
+        let err = fail("\"a\" 1 +");
+        assert!(matches!(err.kind, RuntimeErrorKind::TypeMismatch { .. }));
+        assert_eq!(
+            err.stack,
+            vec![Value::from("a"), Value::Int(1)]
+                .into_iter()
+                .collect::<VecDeque<_>>()
+        );
+    }
+
+    #[test]
+    fn variables_hold_what_to_stored() {
+        let stack = run_on(Vm::new(), "var x 5 to x x x 7 to x x").unwrap();
+        assert_eq!(stack, ints(&[5, 5, 7]));
+    }
+
+    #[test]
+    fn variables_survive_loops_and_calls() {
+        let src = "var s var n 0 to s 3 to n n @ { s n + to s n -1 + to n n } s";
+        assert_eq!(run_on(Vm::new(), src).unwrap(), ints(&[6]));
+        let src = "var c def bump c 1 + to c end 0 to c bump bump c";
+        assert_eq!(run_on(Vm::new(), src).unwrap(), ints(&[2]));
+    }
+
+    #[test]
+    fn reading_an_unassigned_variable_fails() {
+        let err = run_on(Vm::new(), "var x 1 x").unwrap_err();
+        match err.kind {
+            RuntimeErrorKind::UninitializedVariable(name) => assert_eq!(name, "x"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(err.stack, ints(&[1]));
+    }
+
+    #[test]
+    fn zero_uninitialized_reads_unassigned_variables_as_zero() {
+        let mut vm = Vm::new();
+        vm.zero_uninitialized = true;
+        assert_eq!(run_on(vm, "var x x 1 +").unwrap(), ints(&[1]));
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("lang-vm-{}", name));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn bytes(s: &str) -> Value {
+        Value::List(Rc::new(s.bytes().map(|b| Value::Int(b.into())).collect()))
+    }
+
+    #[test]
+    fn files_round_trip_through_write_append_and_read() {
+        let path = temp_path("round-trip");
+        let write = format!(
+            r#""{}" {} fopen let h s in s "ab" h fwrite [ 99 10 ] h fwrite h fclose end"#,
+            path, MODE_WRITE
+        );
+        assert_eq!(run_on(Vm::new(), &write).unwrap(), ints(&[0, 0, 0, 0]));
+        let append = format!(
+            r#""{}" {} fopen let h s in s "d" h fwrite h fclose end"#,
+            path, MODE_APPEND
+        );
+        assert_eq!(run_on(Vm::new(), &append).unwrap(), ints(&[0, 0, 0]));
+        let read = format!(
+            r#""{}" {} fopen let h s in h 3 fread h 100 fread h 100 fread h fclose end"#,
+            path, MODE_READ
+        );
+        let expected = VecDeque::from(vec![
+            bytes("abc"),
+            Value::Int(STATUS_OK),
+            bytes("\nd"),
+            Value::Int(STATUS_OK),
+            bytes(""),
+            Value::Int(STATUS_OK),
+            Value::Int(STATUS_OK),
+        ]);
+        assert_eq!(run_on(Vm::new(), &read).unwrap(), expected);
+    }
+
+    #[test]
+    fn fopen_reports_missing_files_and_bad_modes() {
+        let missing = format!(r#""{}" {} fopen"#, temp_path("missing"), MODE_READ);
+        assert_eq!(
+            run_on(Vm::new(), &missing).unwrap(),
+            ints(&[0, STATUS_NOT_FOUND])
+        );
+        let bad_mode = format!(r#""{}" 7 fopen"#, temp_path("bad-mode"));
+        assert_eq!(
+            run_on(Vm::new(), &bad_mode).unwrap(),
+            ints(&[0, STATUS_INVALID])
+        );
+    }
+
+    #[test]
+    fn unknown_handles_are_reported() {
+        let stack = run_on(Vm::new(), r#"99 2 fread "x" 99 fwrite 99 fclose"#).unwrap();
+        let expected = VecDeque::from(vec![
+            bytes(""),
+            Value::Int(STATUS_BAD_HANDLE),
+            Value::Int(STATUS_BAD_HANDLE),
+            Value::Int(STATUS_BAD_HANDLE),
+        ]);
+        assert_eq!(stack, expected);
+    }
+
+    #[test]
+    fn fwrite_needs_bytes() {
+        let src = format!(
+            r#""{}" {} fopen ; let h in [ 256 ] h fwrite [ -1 ] h fwrite [ "a" ] h fwrite h fclose end"#,
+            temp_path("not-bytes"),
+            MODE_WRITE
+        );
+        let stack = run_on(Vm::new(), &src).unwrap();
+        let invalid = STATUS_INVALID;
+        assert_eq!(stack, ints(&[invalid, invalid, invalid, STATUS_OK]));
+        let err = fail("5 1 fwrite");
+        assert!(matches!(
+            err.kind,
+            RuntimeErrorKind::TypeMismatch {
+                expected: Type::Str,
+                found: Type::Int
+            }
+        ));
+    }
+
+    #[test]
+    fn memory_round_trips_little_endian_at_every_width() {
+        let src = "16 alloc let a in \
+            0x0102030405060708 a store64 a load64 a load8 a 1 + load8 \
+            0xbeef a 8 + store16 a 8 + load16 a 8 + load8 \
+            0x12345678 a 12 + store32 a 12 + load32 a 8 + load64 end";
+        assert_eq!(
+            run_on(Vm::new(), src).unwrap(),
+            ints(&[
+                0x0102030405060708,
+                0x08,
+                0x07,
+                0xbeef,
+                0xef,
+                0x12345678,
+                0x1234_5678_0000_beef,
+            ])
+        );
+    }
+
+    #[test]
+    fn loads_zero_extend_and_stores_truncate() {
+        let src = "8 alloc let a in -1 a store8 a load8 -1 a store16 a load16 \
+            0x1ff a store8 a load8 -1 a store32 a load32 end";
+        assert_eq!(
+            run_on(Vm::new(), src).unwrap(),
+            ints(&[255, 0xffff, 0xff, 0xffff_ffff])
+        );
+    }
+
+    #[test]
+    fn accesses_past_the_allocation_are_out_of_bounds() {
+        assert_eq!(run_on(Vm::new(), "8 alloc 4 + load32").unwrap(), ints(&[0]));
+        for (src, addr, width) in [
+            ("8 alloc 5 + load32", 5, 4),
+            ("8 alloc 8 + load8", 8, 1),
+            ("8 alloc 1 .. 1 + store64", 1, 8),
+            ("-1 load8", -1, 1),
+        ]
+        .iter()
+        {
+            match fail(src).kind {
+                RuntimeErrorKind::OutOfBounds { addr: a, width: w } => {
+                    assert_eq!((a, w), (*addr, *width), "{}", src)
+                }
+                other => panic!("`{}` failed with {:?}", src, other),
+            }
+        }
+    }
+
+    #[test]
+    fn alloc_is_limited_by_memory_size() {
+        let mut vm = Vm::new();
+        vm.memory_size = 32;
+        assert_eq!(
+            run_on(vm, "1 alloc 8 alloc 16 alloc").unwrap(),
+            ints(&[0, 8, 16])
+        );
+        let mut vm = Vm::new();
+        vm.memory_size = 32;
+        let err = run_on(vm, "20 alloc 13 alloc").unwrap_err();
+        assert!(matches!(
+            err.kind,
+            RuntimeErrorKind::OutOfMemory {
+                requested: 13,
+                available: 8
+            }
+        ));
+        assert_eq!(err.stack, ints(&[0, 13]));
+        assert!(matches!(
+            fail("-1 alloc").kind,
+            RuntimeErrorKind::OutOfMemory { requested: -1, .. }
+        ));
+    }
+
+    fn list(items: &[i64]) -> Value {
+        Value::List(Rc::new(items.iter().map(|&n| Value::Int(n)).collect()))
+    }
+
+    fn index_error(src: &str) -> (i64, usize) {
+        match fail(src).kind {
+            RuntimeErrorKind::IndexOutOfRange { index, len } => (index, len),
+            other => panic!("`{}` failed with {:?}", src, other),
+        }
+    }
+
+    #[test]
+    fn list_collects_the_counted_items() {
+        let stack = run_on(Vm::new(), "1 2 3 2 list 0 list").unwrap();
+        assert_eq!(
+            stack,
+            VecDeque::from(vec![Value::Int(1), list(&[2, 3]), list(&[])])
+        );
+        assert!(matches!(
+            fail("1 3 list").kind,
+            RuntimeErrorKind::StackUnderflow {
+                required: 4,
+                available: 2
+            }
+        ));
+        assert!(matches!(
+            fail("-1 list").kind,
+            RuntimeErrorKind::InvalidCount(-1)
+        ));
+    }
+
+    #[test]
+    fn list_get_and_set_check_the_index() {
+        let stack = run_on(Vm::new(), "[ 5 6 ] 1 list_get [ 5 6 ] 1 9 list_set").unwrap();
+        assert_eq!(stack, VecDeque::from(vec![Value::Int(6), list(&[5, 9])]));
+        assert_eq!(index_error("[ 5 6 ] 2 list_get"), (2, 2));
+        assert_eq!(index_error("[ 5 6 ] -1 list_get"), (-1, 2));
+        assert_eq!(index_error("[ ] 0 9 list_set"), (0, 0));
+        let err = fail("[ 5 6 ] 2 9 list_set");
+        assert_eq!(
+            err.stack,
+            VecDeque::from(vec![list(&[5, 6]), Value::Int(2), Value::Int(9)])
+        );
+    }
+
+    #[test]
+    fn list_push_and_pop_work_at_the_end() {
+        let stack = run_on(Vm::new(), "[ 1 ] 2 list_push list_pop").unwrap();
+        assert_eq!(stack, VecDeque::from(vec![list(&[1]), Value::Int(2)]));
+        let err = fail("[ ] list_pop");
+        assert!(matches!(err.kind, RuntimeErrorKind::EmptyList));
+        assert_eq!(err.stack, VecDeque::from(vec![list(&[])]));
+    }
+
+    #[test]
+    fn slices_stop_at_the_end_of_the_list() {
+        let stack = run_on(
+            Vm::new(),
+            "[ 1 2 3 ] 1 10 list_slice [ 1 2 3 ] 3 1 list_slice [ 1 2 3 ] 0 2 list_slice",
+        )
+        .unwrap();
+        assert_eq!(
+            stack,
+            VecDeque::from(vec![list(&[2, 3]), list(&[]), list(&[1, 2])])
+        );
+        assert_eq!(index_error("[ 1 2 3 ] 4 0 list_slice"), (4, 3));
+        assert!(matches!(
+            fail("[ 1 2 3 ] 0 -1 list_slice").kind,
+            RuntimeErrorKind::InvalidCount(-1)
+        ));
+    }
+
+    #[test]
+    fn changing_a_list_leaves_its_copies_alone() {
+        let src = "[ 1 2 ] : 0 9 list_set [ 1 ] : 2 list_push [ 1 ] : list_pop ;";
+        let stack = run_on(Vm::new(), src).unwrap();
+        assert_eq!(
+            stack,
+            VecDeque::from(vec![
+                list(&[1, 2]),
+                list(&[9, 2]),
+                list(&[1]),
+                list(&[1, 2]),
+                list(&[1]),
+                list(&[]),
+            ])
+        );
+    }
+
+    fn strs(items: &[&str]) -> VecDeque<Value> {
+        items.iter().map(|&s| Value::from(s)).collect()
+    }
+
+    #[test]
+    fn substr_and_char_at_count_characters() {
+        let src = r#""héllo wörld" 1 4 substr "héllo wörld" 7 100 substr "λx" 2 0 substr"#;
+        assert_eq!(run_on(Vm::new(), src).unwrap(), strs(&["éllo", "örld", ""]));
+        let src = r#""aλ😀b" 1 char_at "aλ😀b" 2 char_at "aλ😀b" 3 char_at "aλ😀b" str_len"#;
+        assert_eq!(
+            run_on(Vm::new(), src).unwrap(),
+            ints(&['λ' as i64, '😀' as i64, 'b' as i64, 4])
+        );
+        assert_eq!(index_error(r#""aλ" 2 char_at"#), (2, 2));
+        assert_eq!(index_error(r#""aλ" 3 0 substr"#), (3, 2));
+    }
+
+    #[test]
+    fn str_cmp_gives_the_sign_of_the_comparison() {
+        let src = r#""abc" "abd" str_cmp "b" "a" str_cmp "a" "a" str_cmp "a" "ab" str_cmp"#;
+        assert_eq!(run_on(Vm::new(), src).unwrap(), ints(&[-1, 1, 0, -1]));
+    }
+
+    #[test]
+    fn str_cat_joins_in_push_order() {
+        let src = r#""ab" "cd" str_cat "" "λ" str_cat"#;
+        assert_eq!(run_on(Vm::new(), src).unwrap(), strs(&["abcd", "λ"]));
+    }
+
+    #[test]
+    fn to_number_flags_whether_the_text_was_a_number() {
+        let src = r#""42" to_number " -7 " to_number "4x" to_number "" to_number"#;
+        assert_eq!(run_on(Vm::new(), src).unwrap(), ints(&[42, 1, -7, 1, 0, 0]));
+        let src = "-12 to_string [ 1 \"a\" ] to_string";
+        assert_eq!(
+            run_on(Vm::new(), src).unwrap(),
+            strs(&["-12", "[1, \"a\"]"])
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use lang::lexer::lex;
+use lang::parser::{parse, Op, ParseError, ParseErrorKind};
+use lang::vm::compute;
+
+fn run(src: &str) -> Vec<i64> {
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let stack = compute(&program).expect("program should run");
+    stack.iter().map(|v| v.as_int().unwrap()).collect()
+}
+
+fn error(src: &str) -> ParseError {
+    match parse(lex(src, "<test>")) {
+        Ok(_) => panic!("`{}` should not compile", src),
+        Err(mut errors) => errors.remove(0),
+    }
+}
+
+#[test]
+fn constants_build_on_other_constants() {
+    let src = "const W 8 end const H W 2 * end const AREA W H * end AREA H";
+    assert_eq!(run(src), [128, 16]);
+}
+
+#[test]
+fn constant_bodies_may_branch() {
+    assert_eq!(run("const X 1 { 5 }{ 6 } end X"), [5]);
+    assert_eq!(run("const Y 0 { 5 }{ 6 } end Y"), [6]);
+}
+
+#[test]
+fn a_constant_use_compiles_to_one_push() {
+    let program = parse(lex("const X 6 7 * 1 + end X", "<test>")).unwrap();
+    let ops: Vec<Op> = program
+        .ops
+        .iter()
+        .skip(program.entry)
+        .map(|op| op.node)
+        .collect();
+    assert!(matches!(ops[..], [Op::Push(43)]), "{:?}", ops);
+}
+
+#[test]
+fn constants_must_leave_one_value() {
+    let e = error("\n  const PAIR 1 2 end");
+    assert!(
+        matches!(&e.kind, ParseErrorKind::ConstArity { name, count: 2 } if name == "PAIR"),
+        "{:?}",
+        e.kind
+    );
+    assert_eq!((e.span.line, e.span.col), (2, 3));
+    let e = error("const NONE end");
+    assert!(matches!(
+        e.kind,
+        ParseErrorKind::ConstArity { count: 0, .. }
+    ));
+}
+
+#[test]
+fn constants_reject_calls_variables_io_and_loops() {
+    let cases = [
+        ("def f 1 end const A f end", 21),
+        ("var v const A v end", 15),
+        ("const A 1 print 2 end", 11),
+        ("const A 1 @ { 1 } end", 11),
+    ];
+    for (src, col) in cases.iter() {
+        let e = error(src);
+        assert!(
+            matches!(&e.kind, ParseErrorKind::NotConstant(name) if name == "A"),
+            "{}: {:?}",
+            src,
+            e.kind
+        );
+        assert_eq!(e.span.col, *col, "{}", src);
+    }
+}
+
+#[test]
+fn failing_constants_report_the_runtime_error() {
+    let e = error("const A 0 1 / end");
+    assert!(matches!(&e.kind, ParseErrorKind::ConstEval { name, .. } if name == "A"));
+    assert_eq!(
+        e.to_string(),
+        "<test>:1:1: Evaluating constant `A` failed: Division by zero"
+    );
+}
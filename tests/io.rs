@@ -0,0 +1,135 @@
+use lang::lexer::lex;
+use lang::parser::parse;
+use lang::vm::{RuntimeError, RuntimeErrorKind, Value, Vm};
+use std::cell::RefCell;
+use std::io::{self, Cursor, Write};
+use std::rc::Rc;
+
+/// A writer whose bytes stay readable after the `Vm` that owns it is gone.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `src` and returns everything it wrote.
+fn output(src: &str) -> Vec<u8> {
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let out = Shared::default();
+    let mut vm = Vm::new();
+    vm.output = Box::new(out.clone());
+    vm.run(&program).expect("program should run");
+    drop(vm);
+    Rc::try_unwrap(out.0).unwrap().into_inner()
+}
+
+/// Runs `src` reading from `input` and returns the final stack.
+fn run_with_input(src: &str, input: &str) -> Result<Vec<Value>, RuntimeError> {
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let mut vm = Vm::new();
+    vm.input = Box::new(Cursor::new(input.as_bytes().to_vec()));
+    vm.output = Box::new(io::sink());
+    vm.run(&program)?;
+    Ok(vm.stack.into_iter().collect())
+}
+
+fn ints(ns: &[i64]) -> Vec<Value> {
+    ns.iter().map(|&n| Value::from(n)).collect()
+}
+
+#[test]
+fn print_writes_integers_without_separators() {
+    assert_eq!(output("1 print -23 print"), b"1-23");
+}
+
+#[test]
+fn print_writes_strings_as_their_text() {
+    assert_eq!(
+        output(r#""héllo, \"you\"" print"#),
+        "héllo, \"you\"".as_bytes()
+    );
+}
+
+#[test]
+fn emit_writes_characters_as_utf8() {
+    assert_eq!(output("104 emit 105 emit 955 emit"), "hiλ".as_bytes());
+}
+
+#[test]
+fn newline_writes_a_line_feed() {
+    assert_eq!(output("1 print newline newline"), b"1\n\n");
+}
+
+#[test]
+fn print_stack_writes_the_stack_and_leaves_it() {
+    assert_eq!(
+        output(r#"1 "a" -2 print_stack print_stack"#),
+        b"[1, \"a\", -2]\n[1, \"a\", -2]\n"
+    );
+}
+
+#[test]
+fn read_int_reads_words_until_eof() {
+    let stack = run_with_input("read_int read_int read_int", "12 -3").unwrap();
+    assert_eq!(stack, ints(&[12, 1, -3, 1, 0]));
+}
+
+#[test]
+fn read_int_skips_leading_whitespace_and_newlines() {
+    let stack = run_with_input("read_int read_int", "\n  7\n\t8\n").unwrap();
+    assert_eq!(stack, ints(&[7, 1, 8, 1]));
+}
+
+#[test]
+fn read_int_rejects_words_that_are_not_numbers() {
+    let err = run_with_input("5 read_int", "12x").unwrap_err();
+    assert!(matches!(err.kind, RuntimeErrorKind::NotANumber));
+    assert_eq!(err.stack, ints(&[5]));
+}
+
+#[test]
+fn read_char_decodes_multibyte_utf8() {
+    let stack = run_with_input("read_char read_char read_char read_char", "λ€😀").unwrap();
+    assert_eq!(stack, ints(&[0x3bb, 1, 0x20ac, 1, 0x1f600, 1, 0]));
+}
+
+#[test]
+fn read_char_rejects_invalid_utf8() {
+    let program = parse(lex("read_char", "<test>")).unwrap();
+    let mut vm = Vm::new();
+    vm.input = Box::new(Cursor::new(vec![0xce]));
+    let err = vm.run(&program).unwrap_err();
+    assert!(matches!(err.kind, RuntimeErrorKind::InvalidUtf8));
+}
+
+#[test]
+fn read_line_strips_crlf() {
+    let stack = run_with_input("read_line read_line read_line", "a b\r\nc\n").unwrap();
+    assert_eq!(
+        stack,
+        vec![
+            Value::from("a b"),
+            Value::from(1),
+            Value::from("c"),
+            Value::from(1),
+            Value::from(0),
+        ]
+    );
+}
+
+#[test]
+fn read_line_keeps_a_last_line_without_terminator() {
+    let stack = run_with_input("read_line read_line", "end").unwrap();
+    assert_eq!(
+        stack,
+        vec![Value::from("end"), Value::from(1), Value::from(0)]
+    );
+}
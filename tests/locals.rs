@@ -0,0 +1,82 @@
+use lang::lexer::lex;
+use lang::parser::{parse, ParseErrorKind};
+use lang::vm::compute;
+
+fn run(src: &str) -> Vec<i64> {
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let stack = compute(&program).expect("program should run");
+    stack.iter().map(|v| v.as_int().unwrap()).collect()
+}
+
+fn errors(src: &str) -> Vec<ParseErrorKind> {
+    match parse(lex(src, "<test>")) {
+        Ok(_) => panic!("`{}` should not compile", src),
+        Err(errors) => errors.into_iter().map(|e| e.kind).collect(),
+    }
+}
+
+#[test]
+fn let_binds_in_push_order() {
+    assert_eq!(run("1 2 3 let a b c in c b a end"), [3, 2, 1]);
+    assert_eq!(run("7 let x in x x end"), [7, 7]);
+}
+
+#[test]
+fn locals_do_not_leak_out_of_their_scope() {
+    let errs = errors("1 let a in end a");
+    assert!(matches!(&errs[..], [ParseErrorKind::UnknownWord { word, .. }] if word == "a"));
+    let errs = errors("def f 1 let a in end end def g a end");
+    assert!(matches!(&errs[..], [ParseErrorKind::UnknownWord { word, .. }] if word == "a"));
+}
+
+#[test]
+fn inner_locals_shadow_outer_ones() {
+    assert_eq!(run("1 let a in 2 let a in a end a end"), [2, 1]);
+}
+
+#[test]
+fn locals_survive_recursion() {
+    let src = "
+def gcd
+  let a b in
+    b 0 == { a }{ b b a % gcd }
+  end
+end
+48 18 gcd 7 5 gcd";
+    assert_eq!(run(src), [6, 1]);
+    let src = "
+def fact
+  let n in
+    0 n > { 1 n - fact n * }{ 1 }
+  end
+end
+5 fact";
+    assert_eq!(run(src), [120]);
+}
+
+#[test]
+fn to_assigns_a_local() {
+    assert_eq!(run("1 let a in a 10 + to a a end"), [11]);
+    assert_eq!(
+        run("0 5 let sum n in n @ { sum n + to sum 1 n - to n n } sum end"),
+        [15]
+    );
+}
+
+#[test]
+fn closing_a_block_ends_the_lets_opened_in_it() {
+    for src in [
+        "1 2 3 { let a in let b in } end end",
+        "def f 1 2 3 { let a in let b in } end end end",
+        "[ 1 2 3 { let a in let b in } end end ]",
+    ]
+    .iter()
+    {
+        let errs = errors(src);
+        let across = errs
+            .iter()
+            .filter(|k| matches!(k, ParseErrorKind::LetAcrossBlock { .. }))
+            .count();
+        assert_eq!(across, 2, "{}: {:?}", src, errs);
+    }
+}
@@ -0,0 +1,50 @@
+use lang::lexer::lex;
+use lang::parser::parse;
+use lang::vm::compute;
+
+#[test]
+fn parse_errors_in_expansions_name_the_use_site() {
+    let src = "macro m in foo end\n\nm\nmacro n in { 1 end\n n";
+    let errors = parse(lex(src, "<test>")).unwrap_err();
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages.len(), 2, "{:?}", messages);
+    assert_eq!(
+        messages[0],
+        "<test>:4:12: `{` is never closed (in expansion of `n` at <test>:5:2)"
+    );
+    assert!(messages[1].starts_with("<test>:1:12: Unknown word `foo`"));
+    assert!(messages[1].ends_with(" (in expansion of `m` at <test>:3:1)"));
+}
+
+#[test]
+fn runtime_errors_in_expansions_name_the_use_site() {
+    let src = "macro m in 0 1 / end\n\n m";
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let err = compute(&program).unwrap_err();
+    assert_eq!(err.op.span.line, 1);
+    assert!(err
+        .to_string()
+        .ends_with(" (in expansion of `m` at <test>:3:2)"));
+}
+
+#[test]
+fn nested_expansions_report_the_outermost_use() {
+    let src = "macro inner in foo end\nmacro outer in inner end\n\n  outer";
+    let errors = parse(lex(src, "<test>")).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0]
+        .to_string()
+        .ends_with(" (in expansion of `outer` at <test>:4:3)"));
+}
+
+#[test]
+fn locals_shadow_macros() {
+    let src = "macro m in 1 end 5 let m in m 2 + to m m end m";
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let stack: Vec<i64> = compute(&program)
+        .unwrap()
+        .iter()
+        .map(|v| v.as_int().unwrap())
+        .collect();
+    assert_eq!(stack, [7, 1]);
+}
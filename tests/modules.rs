@@ -0,0 +1,89 @@
+use lang::lexer::lex;
+use lang::parser::{ParseError, ParseErrorKind, Parser, Program};
+use lang::vm::compute;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes `files` into a fresh directory and compiles `main` as if it were a file there.
+fn compile(dir: &str, files: &[(&str, &str)], main: &str) -> Result<Program, Vec<ParseError>> {
+    let dir: PathBuf = std::env::temp_dir().join(dir);
+    for (name, src) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, src).unwrap();
+    }
+    let mut parser = Parser::new();
+    parser.load_prelude();
+    let path = dir.join("main.lang");
+    lex(main, &path.to_string_lossy()).for_each(|tok| parser.feed(tok));
+    parser.finish()
+}
+
+fn run(dir: &str, files: &[(&str, &str)], main: &str) -> Vec<i64> {
+    let program = compile(dir, files, main).expect("program should compile");
+    let stack = compute(&program).expect("program should run");
+    stack.iter().map(|v| v.as_int().unwrap()).collect()
+}
+
+#[test]
+fn forward_references_stay_in_the_module() {
+    let m2 = "def f g end def g 99 end";
+    let stack = run(
+        "lang-modules-fwd",
+        &[("m2.lang", m2)],
+        r#"def g 1 end import "m2.lang" m2.f g"#,
+    );
+    assert_eq!(stack, [99, 1]);
+}
+
+#[test]
+fn module_definitions_shadow_the_prelude() {
+    let m = "def f abs end def abs 99 end";
+    let stack = run(
+        "lang-modules-prelude",
+        &[("m.lang", m)],
+        r#"import "m.lang" m.f -3 abs"#,
+    );
+    assert_eq!(stack, [99, 3]);
+}
+
+#[test]
+fn undefined_module_words_fall_back_to_top_level() {
+    let m = "def f g sq end";
+    let stack = run(
+        "lang-modules-outer",
+        &[("m.lang", m)],
+        r#"def g 7 end import "m.lang" m.f"#,
+    );
+    assert_eq!(stack, [49]);
+}
+
+#[test]
+fn modules_with_the_same_name_clash() {
+    let files = [
+        ("a/util.lang", "def x 1 end"),
+        ("b/util.lang", "def y 2 end"),
+    ];
+    let main = "import \"a/util.lang\"\nimport \"b/util.lang\" util.x";
+    let errors = compile("lang-modules-clash", &files, main).unwrap_err();
+    match &errors[..] {
+        [e] => {
+            match &e.kind {
+                ParseErrorKind::ModuleClash { module, first } => {
+                    assert_eq!(module, "util");
+                    assert!(Path::new(first).ends_with("a/util.lang"), "{}", first);
+                }
+                other => panic!("{:?}", other),
+            }
+            assert_eq!((e.span.line, e.span.col), (2, 1));
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn importing_the_same_file_twice_reads_it_once() {
+    let files = [("d/util.lang", "def x 1 end")];
+    let main = "import \"d/util.lang\" import \"d/../d/util.lang\" util.x";
+    assert_eq!(run("lang-modules-twice", &files, main), [1]);
+}
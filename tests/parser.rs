@@ -0,0 +1,115 @@
+use lang::lexer::lex;
+use lang::parser::{ParseError, ParseErrorKind, Parser};
+
+fn errors(src: &str) -> Vec<ParseError> {
+    let mut parser = Parser::new();
+    parser.load_prelude();
+    lex(src, "<test>").for_each(|tok| parser.feed(tok));
+    parser.finish().expect_err("program should not compile")
+}
+
+/// Each error as its line, column and kind, without the kind's fields.
+fn summary(errors: &[ParseError]) -> Vec<(usize, usize, String)> {
+    errors
+        .iter()
+        .map(|e| {
+            let kind = format!("{:?}", e.kind);
+            let name = kind.split(|c: char| !c.is_alphanumeric()).next().unwrap();
+            (e.span.line, e.span.col, name.to_string())
+        })
+        .collect()
+}
+
+fn suggestions(src: &str) -> Vec<String> {
+    match &errors(src)[..] {
+        [ParseError {
+            kind: ParseErrorKind::UnknownWord { suggestions, .. },
+            ..
+        }] => suggestions.clone(),
+        other => panic!("expected one unknown word, got {:?}", other),
+    }
+}
+
+#[test]
+fn errors_are_reported_together_with_their_spans() {
+    let src = "\
+}{
+}
+1 { 2 }{ 3 }{ 4 }
+0b102 dupp
+def f 1 end
+def f 2 end
+]
+end
+1 {
+";
+    assert_eq!(
+        summary(&errors(src)),
+        [
+            (1, 1, "UnmatchedElse".to_string()),
+            (2, 1, "UnmatchedEnd".to_string()),
+            (3, 12, "DuplicateElse".to_string()),
+            (4, 1, "Lex".to_string()),
+            (6, 1, "Redefinition".to_string()),
+            (7, 1, "StrayBracket".to_string()),
+            (8, 1, "StrayEnd".to_string()),
+            (9, 3, "UnclosedBlock".to_string()),
+            (4, 7, "UnknownWord".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn duplicate_else_points_at_the_first() {
+    match &errors("1 {\n2 }{ 3\n}{ 4 }")[..] {
+        [ParseError {
+            kind: ParseErrorKind::DuplicateElse { first },
+            span,
+        }] => {
+            assert_eq!((first.line, first.col), (2, 3));
+            assert_eq!((span.line, span.col), (3, 1));
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn code_after_an_error_is_still_checked() {
+    let errs = errors("}\nfoo\n{ bar }\n");
+    assert_eq!(
+        summary(&errs),
+        [
+            (1, 1, "UnmatchedEnd".to_string()),
+            (2, 1, "UnknownWord".to_string()),
+            (3, 3, "UnknownWord".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn suggestions_allow_one_edit_per_three_characters() {
+    assert_eq!(suggestions("prnit"), ["print"]);
+    assert_eq!(suggestions("pritn_stak"), ["print_stack"]);
+    assert_eq!(suggestions("def square : * end 2 sqaure"), ["square"]);
+    assert!(suggestions("pr_stack").is_empty());
+    assert!(suggestions("dupp").is_empty());
+}
+
+#[test]
+fn suggestions_never_replace_the_whole_word() {
+    assert!(suggestions("x").is_empty());
+    assert!(suggestions("xy").is_empty());
+}
+
+#[test]
+fn suggestions_are_nearest_first_and_at_most_three() {
+    assert_eq!(suggestions("mix"), ["max", "min"]);
+    assert_eq!(
+        suggestions("def aa 1 end def ab 1 end def ac 1 end def ad 1 end a_"),
+        ["aa", "ab", "ac"]
+    );
+    assert_eq!(
+        suggestions("def aravo 1 end def bravo 1 end bravoo"),
+        ["bravo", "aravo"]
+    );
+}
@@ -0,0 +1,105 @@
+use lang::lexer::lex;
+use lang::parser::Parser;
+use lang::vm::compute;
+
+fn run(src: &str) -> Vec<i64> {
+    let mut parser = Parser::new();
+    parser.load_prelude();
+    lex(src, "<test>").for_each(|tok| parser.feed(tok));
+    let program = parser.finish().expect("program should compile");
+    let stack = compute(&program).expect("program should run");
+    stack
+        .iter()
+        .map(|v| v.as_int().expect("only integers"))
+        .collect()
+}
+
+#[test]
+fn prelude_compiles_on_its_own() {
+    assert_eq!(run(""), Vec::<i64>::new());
+}
+
+#[test]
+fn stack_shuffles() {
+    assert_eq!(run("1 2 nip"), [2]);
+    assert_eq!(run("1 2 tuck"), [2, 1, 2]);
+    assert_eq!(run("1 2 2dup"), [1, 2, 1, 2]);
+    assert_eq!(run("1 2 3 2drop"), [1]);
+    assert_eq!(run("1 2 3 unrot"), [3, 1, 2]);
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(run("5 neg -5 neg 0 neg"), [-5, 5, 0]);
+    assert_eq!(run("5 inc -1 inc"), [6, 0]);
+    assert_eq!(run("5 dec 0 dec"), [4, -1]);
+    assert_eq!(run("7 sq -3 sq"), [49, 9]);
+}
+
+#[test]
+fn abs_and_sign() {
+    assert_eq!(run("-7 abs 7 abs 0 abs"), [7, 7, 0]);
+    assert_eq!(run("-4 sign 0 sign 9 sign"), [-1, 0, 1]);
+}
+
+#[test]
+fn min_and_max() {
+    assert_eq!(run("3 9 min 9 3 min -2 -2 min"), [3, 3, -2]);
+    assert_eq!(run("3 9 max 9 3 max -2 -2 max"), [9, 9, -2]);
+}
+
+#[test]
+fn gcd() {
+    assert_eq!(run("12 18 gcd 18 12 gcd"), [6, 6]);
+    assert_eq!(run("7 0 gcd 0 7 gcd"), [7, 7]);
+    assert_eq!(run("-12 18 gcd 17 5 gcd"), [6, 1]);
+}
+
+#[test]
+fn logic() {
+    assert_eq!(run("0 not 5 not"), [1, 0]);
+    assert_eq!(
+        run("1 1 and 1 0 and 0 1 and 0 0 and 3 4 and"),
+        [1, 0, 0, 0, 1]
+    );
+    assert_eq!(run("1 1 or 1 0 or 0 1 or 0 0 or"), [1, 1, 1, 0]);
+}
+
+#[test]
+fn parity() {
+    assert_eq!(run("4 even 5 even 0 even -3 even"), [1, 0, 1, 0]);
+    assert_eq!(run("4 odd 5 odd -3 odd"), [0, 1, 1]);
+}
+
+#[test]
+fn dip_keep_bi() {
+    assert_eq!(run("1 2 [ 10 * ] dip"), [10, 2]);
+    assert_eq!(run("5 [ sq ] keep"), [25, 5]);
+    assert_eq!(run("6 [ inc ] [ dec ] bi"), [7, 5]);
+}
+
+#[test]
+fn times() {
+    assert_eq!(run("[ 7 ] 3 times"), [7, 7, 7]);
+    assert_eq!(run("1 [ 2 * ] 10 times"), [1024]);
+    assert_eq!(run("1 [ 2 * ] 0 times"), [1]);
+}
+
+#[test]
+fn each_map_filter_fold() {
+    assert_eq!(run("0 [1 2 3] [ + ] each"), [6]);
+    assert_eq!(run("[1 2 3] [ 2 * ] map call"), [2, 4, 6]);
+    assert_eq!(run("[1 2 3 4 5] [ even ] filter call"), [2, 4]);
+    assert_eq!(run("[1 2 3 4] 0 [ + ] fold"), [10]);
+    assert_eq!(run("[ ] [ 2 * ] map list_len"), [0]);
+}
+
+#[test]
+fn user_definitions_shadow_the_prelude() {
+    assert_eq!(run("def abs 42 end -5 abs"), [-5, 42]);
+    assert_eq!(run("def f -5 abs end def abs 42 end f"), [-5, 42]);
+    assert_eq!(run("macro sq x in x x + end 3 sq 4"), [3, 8]);
+    assert_eq!(run("const F_OK 9 end F_OK"), [9]);
+    // Prelude words keep calling the prelude's own versions.
+    assert_eq!(run("def abs 42 end -12 18 gcd"), [6]);
+}
@@ -0,0 +1,77 @@
+use lang::lexer::lex;
+use lang::parser::{parse, ParseErrorKind};
+use lang::vm::{compute, RuntimeErrorKind, MAX_CALL_DEPTH};
+
+fn run(src: &str) -> Vec<i64> {
+    let program = parse(lex(src, "<test>")).expect("program should compile");
+    let stack = compute(&program).expect("program should run");
+    stack.iter().map(|v| v.as_int().unwrap()).collect()
+}
+
+#[test]
+fn words_can_recurse() {
+    assert_eq!(run("def down : { -1 + down } end 5 down"), [0]);
+    assert_eq!(run("def tri : { : -1 + tri + } end 4 tri"), [10]);
+}
+
+#[test]
+fn words_can_be_called_before_their_definition() {
+    assert_eq!(run("def a b 1 + end def b 41 end a"), [42]);
+    let src = "
+def ev : { -1 + od }{ ; 1 } end
+def od : { -1 + ev }{ ; 0 } end
+7 ev 6 ev";
+    assert_eq!(run(src), [0, 1]);
+}
+
+#[test]
+fn redefining_a_word_is_an_error() {
+    let errors = parse(lex("def f 1 end\ndef f 2 end", "<test>")).unwrap_err();
+    match &errors[..] {
+        [e] => {
+            match &e.kind {
+                ParseErrorKind::Redefinition { name, first } => {
+                    assert_eq!(name, "f");
+                    assert_eq!((first.line, first.col), (1, 1));
+                }
+                other => panic!("{:?}", other),
+            }
+            assert_eq!(e.span.line, 2);
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn unbounded_recursion_overflows_the_call_stack() {
+    let program = parse(lex("def f f end f", "<test>")).unwrap();
+    let err = compute(&program).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        RuntimeErrorKind::CallStackOverflow(MAX_CALL_DEPTH)
+    ));
+}
+
+#[test]
+fn blocks_inside_a_loop_do_not_end_it() {
+    // ( count n ) with a skipped and a taken block in every pass.
+    assert_eq!(run("0 3 : @ { .. 1 + .. 0 { 100 } -1 + : } ;"), [3]);
+    assert_eq!(run("0 3 : @ { .. 1 + .. 1 { } -1 + : } ;"), [3]);
+    assert_eq!(run("0 3 : @ { 0 { }{ .. 1 + .. } -1 + : } ;"), [3]);
+}
+
+#[test]
+fn only_the_block_after_the_loop_repeats() {
+    assert_eq!(run("0 @ { 1 } 1 { 2 } 3"), [2, 3]);
+    assert_eq!(
+        run("var c 0 to c 3 : @ { 2 : @ { c 1 + to c -1 + : } ; -1 + : } ; c"),
+        [6]
+    );
+}
+
+#[test]
+fn words_called_in_a_loop_keep_their_own_loops() {
+    assert_eq!(run("def inc2 2 + end 0 3 : @ { .. inc2 .. -1 + : } ;"), [6]);
+    let src = "def spin 4 : @ { -1 + : } ; end 0 3 : @ { spin .. 1 + .. -1 + : } ;";
+    assert_eq!(run(src), [3]);
+}